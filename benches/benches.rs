@@ -5,11 +5,51 @@ extern crate test;
 extern crate pulse;
 extern crate future_pulse;
 
+use std::alloc::{GlobalAlloc, System, Layout};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
 use fibe::*;
 use test::Bencher;
 use pulse::Signals;
 use future_pulse::Future;
 
+/// Counts the allocations while `COUNTING` is set, to see what a
+/// task costs
+struct Counting;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Print the number of allocations made by all the threads per run
+/// of `f`, counted before the benchmark so it is not slowed down
+fn count_allocations<F: FnMut()>(name: &str, f: &mut F) {
+    const RUNS: usize = 1_000;
+    ALLOCATIONS.store(0, Ordering::SeqCst);
+    COUNTING.store(true, Ordering::SeqCst);
+    for _ in 0..RUNS {
+        f();
+    }
+    COUNTING.store(false, Ordering::SeqCst);
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst);
+    println!("{}: {:.1} allocations per run", name, allocations as f64 / RUNS as f64);
+}
+
 fn warmup(front: &mut fibe::Frontend) {
     for _ in 0..100 {
         task(|_| {}).start(front);
@@ -75,6 +115,38 @@ fn chain_1_000_wait(b: &mut Bencher) {
     });
 }
 
+/// A chain on a single worker, every task runs from the next task slot
+#[bench]
+fn chain_1_000_wait_1_worker(b: &mut Bencher) {
+    let mut front = FrontendBuilder::new().workers(1).build();
+    warmup(&mut front);
+    b.iter(|| {
+        let mut last = task(move |_| {}).start(&mut front);
+        for _ in 1..1_000 {
+            last = task(move |_| {}).after(last.signal()).start(&mut front);
+        }
+        last.wait().unwrap();
+    });
+}
+
+/// A chain on a single worker next to queued tasks, the chain has to
+/// leave the next task slot for the queued tasks to run
+#[bench]
+fn chain_1_000_with_queued_tasks(b: &mut Bencher) {
+    let mut front = FrontendBuilder::new().workers(1).build();
+    warmup(&mut front);
+    b.iter(|| {
+        let mut last = task(move |_| {}).start(&mut front);
+        let mut others = Vec::new();
+        for _ in 1..1_000 {
+            last = task(move |_| {}).after(last.signal()).start(&mut front);
+            others.push(task(move |_| {}).start(&mut front).signal());
+        }
+        others.push(last.signal());
+        pulse::Barrier::new(&others).wait().unwrap();
+    });
+}
+
 
 fn fibb_steal(depth: usize, front: &mut fibe::Frontend) -> Future<u64> {
     let task = task(move |_| {1});
@@ -96,6 +168,17 @@ fn bench_fibb_steal(b: &mut Bencher) {
     });
 }
 
+/// The tree on a single worker, where the next task slot is the only
+/// difference with the plain queue
+#[bench]
+fn bench_fibb_steal_1_worker(b: &mut Bencher) {
+    let mut front = FrontendBuilder::new().workers(1).build();
+    warmup(&mut front);
+    b.iter(|| {
+        fibb_steal(8, &mut front).wait().unwrap();
+    });
+}
+
 #[bench]
 fn fanout_1_000(b: &mut Bencher) {
     let mut front = fibe::Frontend::new();
@@ -175,17 +258,63 @@ fn spawn(b: &mut Bencher) {
     let mut front = Frontend::new();
     warmup(&mut front);
 
+    let mut run = || task(|_| {}).start(&mut front).get();
+    count_allocations("spawn", &mut run);
+    b.iter(run);
+}
+
+#[bench]
+fn spawn_get(b: &mut Bencher) {
+    let mut front = Frontend::new();
+    warmup(&mut front);
+
+    let mut run = || { task(|_| {}).start(&mut front); };
+    count_allocations("spawn_get", &mut run);
+    b.iter(run);
+}
+
+#[bench]
+fn spawn_from_4_threads(b: &mut Bencher) {
+    let mut front = Frontend::new();
+    warmup(&mut front);
+
+    // every task is submitted from outside of the pool, so they all
+    // go through the global queue
     b.iter(|| {
-        task(|_| {}).start(&mut front).get();
+        let threads: Vec<_> = (0..4).map(|_| {
+            let mut spawner = front.spawner();
+            std::thread::spawn(move || {
+                let signals: Vec<pulse::Signal> = (0..250).map(|_|
+                    task(|_| {}).start(&mut spawner).signal()
+                ).collect();
+                pulse::Barrier::new(&signals).wait().unwrap();
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
     });
 }
 
+/// A closure too large to be stored inline, submitted from outside of
+/// the pool. Its block is dropped by the pool and comes back
 #[bench]
-fn spawn_get(b: &mut Bencher) {
+fn spawn_block(b: &mut Bencher) {
+    let mut front = Frontend::new();
+    warmup(&mut front);
+
+    let data = [1usize; 16];
+    let mut run = || task(move |_| data[0]).start(&mut front).get();
+    count_allocations("spawn_block", &mut run);
+    b.iter(run);
+}
+
+#[bench]
+fn spawn_no_suspend(b: &mut Bencher) {
     let mut front = Frontend::new();
     warmup(&mut front);
 
     b.iter(|| {
-        task(|_| {}).start(&mut front);
+        task(|_| {}).no_suspend().start(&mut front).get();
     });
-}
\ No newline at end of file
+}
@@ -0,0 +1,52 @@
+//! Pinning of the worker threads to CPU cores.
+
+/// The number of cores a thread can be pinned to.
+#[cfg(target_os="linux")]
+pub const MAX_CORES: usize = ::libc::CPU_SETSIZE as usize;
+
+/// The number of cores a thread can be pinned to.
+#[cfg(not(target_os="linux"))]
+pub const MAX_CORES: usize = ::std::usize::MAX;
+
+/// Restrict the calling thread to the listed cores.
+#[cfg(target_os="linux")]
+pub fn pin_current_thread(cores: &[usize]) {
+    use std::mem;
+    use libc;
+
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for &core in cores {
+            libc::CPU_SET(core, &mut set);
+        }
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            warn!("Could not pin the thread to the cores {:?}", cores);
+        }
+    }
+}
+
+/// The core the calling thread is running on.
+#[cfg(all(target_os="linux", feature="fiber"))]
+pub fn current_core() -> Option<usize> {
+    use libc;
+
+    let core = unsafe { libc::sched_getcpu() };
+    if core < 0 {
+        None
+    } else {
+        Some(core as usize)
+    }
+}
+
+/// Restrict the calling thread to the listed cores.
+#[cfg(not(target_os="linux"))]
+pub fn pin_current_thread(cores: &[usize]) {
+    warn!("Pinning threads to the cores {:?} is not supported on this platform", cores);
+}
+
+/// The core the calling thread is running on.
+#[cfg(all(not(target_os="linux"), feature="fiber"))]
+pub fn current_core() -> Option<usize> {
+    None
+}
@@ -0,0 +1,67 @@
+//! Per frame time budget. Once the budget of the current frame is
+//! spent, deferrable tasks are held over to the next frame instead of
+//! being started.
+
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use TaskOptions;
+use job::Job;
+
+pub type HeldTask = (Job, TaskOptions);
+
+struct State {
+    budget: Option<Duration>,
+    start: Instant,
+    held: Vec<HeldTask>,
+    postponed: usize
+}
+
+pub struct Budget(Mutex<State>);
+
+impl Budget {
+    pub fn new() -> Budget {
+        Budget(Mutex::new(State {
+            budget: None,
+            start: Instant::now(),
+            held: Vec::new(),
+            postponed: 0
+        }))
+    }
+
+    /// Set the time budget of a frame, `None` disables it.
+    pub fn set(&self, budget: Option<Duration>) {
+        self.0.lock().unwrap().budget = budget;
+    }
+
+    /// Hold a ready task if it is deferrable and the frame's budget is
+    /// spent. Gives the task back if it should be started now.
+    pub fn hold(&self, task: Job, options: TaskOptions) -> Option<HeldTask> {
+        if !options.deferrable {
+            return Some((task, options));
+        }
+
+        let mut state = self.0.lock().unwrap();
+        match state.budget {
+            Some(budget) if state.start.elapsed() >= budget => {
+                state.held.push((task, options));
+                state.postponed += 1;
+                None
+            }
+            _ => Some((task, options))
+        }
+    }
+
+    /// Start a new frame, returns the tasks that were held over.
+    pub fn next_frame(&self) -> Vec<HeldTask> {
+        let mut state = self.0.lock().unwrap();
+        state.start = Instant::now();
+        mem::replace(&mut state.held, Vec::new())
+    }
+
+    /// The number of tasks that were held over since the start.
+    pub fn postponed(&self) -> usize {
+        self.0.lock().unwrap().postponed
+    }
+}
@@ -0,0 +1,142 @@
+//! Cache of tasks, so that tasks computing the same data are only
+//! started once.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use future_pulse::Future;
+
+use {Schedule, SharedFuture};
+use task::task;
+
+/// When the cached results are dropped.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Eviction {
+    /// Drop the results last requested `n` frames ago or more, see
+    /// `TaskCache::next_frame`. `Frames(1)` clears the cache with
+    /// every frame.
+    Frames(u64),
+    /// Keep at most `n` results, dropping the least recently
+    /// requested ones first.
+    Lru(usize),
+}
+
+struct Entry<T> {
+    future: SharedFuture<T>,
+    /// The frame the entry was last requested in
+    frame: u64,
+    /// When the entry was last requested, to find the least recent
+    used: u64
+}
+
+struct State<K, T> {
+    entries: HashMap<K, Entry<T>>,
+    frame: u64,
+    clock: u64
+}
+
+/// Shares the tasks computing the same data, the key identifies the
+/// data. A task that is in flight or completed is reused rather than
+/// started again, until its result is evicted.
+pub struct TaskCache<K, T> {
+    eviction: Eviction,
+    state: Mutex<State<K, T>>
+}
+
+impl<K: Hash + Eq, T: Send + 'static> TaskCache<K, T> {
+    /// Create an empty cache.
+    pub fn new(eviction: Eviction) -> TaskCache<K, T> {
+        match eviction {
+            Eviction::Frames(n) => assert!(n > 0, "results must be kept for a frame"),
+            Eviction::Lru(n) => assert!(n > 0, "the cache must hold a result")
+        }
+        TaskCache {
+            eviction: eviction,
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                frame: 0,
+                clock: 0
+            })
+        }
+    }
+
+    /// The result of the task for `key`. The task runs `f`, it is only
+    /// started if there is no task for `key` in the cache.
+    pub fn get_or_spawn<F>(&self, key: K, sched: &mut Schedule, f: F) -> SharedFuture<T>
+        where F: FnOnce(&mut Schedule) -> T + Send + 'static {
+
+        // the entry is added before the task is started, since starting
+        // may wait for the pool, which must not be done holding the lock
+        let (future, set) = {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let (frame, clock) = (state.frame, state.clock);
+            if let Some(entry) = state.entries.get_mut(&key) {
+                entry.frame = frame;
+                entry.used = clock;
+                return entry.future.clone();
+            }
+
+            let (future, set) = Future::new();
+            let future = SharedFuture::new(future);
+            state.entries.insert(key, Entry {
+                future: future.clone(),
+                frame: frame,
+                used: clock
+            });
+            if let Eviction::Lru(max) = self.eviction {
+                while state.entries.len() > max {
+                    evict_oldest(&mut state.entries);
+                }
+            }
+            (future, set)
+        };
+
+        task(move |sched| set.set(f(sched))).start(sched);
+        future
+    }
+
+    /// The result for `key`, if it is in the cache.
+    pub fn get(&self, key: &K) -> Option<SharedFuture<T>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let (frame, clock) = (state.frame, state.clock);
+        state.entries.get_mut(key).map(|entry| {
+            entry.frame = frame;
+            entry.used = clock;
+            entry.future.clone()
+        })
+    }
+
+    /// Drop the result for `key`, the next request starts a new task.
+    pub fn remove(&self, key: &K) {
+        self.state.lock().unwrap().entries.remove(key);
+    }
+
+    /// Start a new frame, with `Eviction::Frames` this drops the results
+    /// that were not requested recently enough.
+    pub fn next_frame(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.frame += 1;
+        if let Eviction::Frames(n) = self.eviction {
+            let frame = state.frame;
+            state.entries.retain(|_, entry| frame - entry.frame < n);
+        }
+    }
+
+    /// The number of results in the cache.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+}
+
+/// Drop the least recently requested entry.
+fn evict_oldest<K: Hash + Eq, T>(entries: &mut HashMap<K, Entry<T>>) {
+    let oldest = entries.iter()
+                        .min_by_key(|&(_, entry)| entry.used)
+                        .map(|(_, entry)| entry.used);
+    if let Some(oldest) = oldest {
+        entries.retain(|_, entry| entry.used != oldest);
+    }
+}
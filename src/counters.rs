@@ -0,0 +1,124 @@
+//! Counters of the tasks going through a back-end, used to wait for
+//! them on shutdown and to report what happened to them.
+
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::time::Instant;
+
+use ShutdownReport;
+
+pub struct Counters {
+    added: AtomicUsize,
+    ready: AtomicUsize,
+    started: AtomicUsize,
+    finished: AtomicUsize,
+    waiting: AtomicBool,
+    lock: Mutex<()>,
+    changed: Condvar
+}
+
+/// Counts the task as finished when dropped, after it ran or
+/// while unwinding from a panic.
+pub struct Finished(Arc<Counters>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        self.0.finished.fetch_add(1, Ordering::SeqCst);
+        if self.0.waiting.load(Ordering::SeqCst) {
+            let _guard = self.0.lock.lock().unwrap();
+            self.0.changed.notify_all();
+        }
+    }
+}
+
+impl Counters {
+    pub fn new() -> Arc<Counters> {
+        Arc::new(Counters {
+            added: AtomicUsize::new(0),
+            ready: AtomicUsize::new(0),
+            started: AtomicUsize::new(0),
+            finished: AtomicUsize::new(0),
+            waiting: AtomicBool::new(false),
+            lock: Mutex::new(()),
+            changed: Condvar::new()
+        })
+    }
+
+    /// Count a new task.
+    pub fn add(&self) {
+        self.added.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Count a task as started, the returned guard counts it as
+    /// finished.
+    pub fn start(this: &Arc<Counters>) -> Finished {
+        this.started.fetch_add(1, Ordering::SeqCst);
+        Finished(this.clone())
+    }
+
+    /// Count a task whose dependencies are completed.
+    pub fn ready(&self) {
+        self.ready.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The number of tasks that are ready but did not start yet.
+    #[cfg(feature="fiber")]
+    pub fn queued(&self) -> usize {
+        let started = self.started.load(Ordering::SeqCst);
+        let ready = self.ready.load(Ordering::SeqCst);
+        ready.saturating_sub(started)
+    }
+
+    fn wait_until<F>(&self, deadline: Option<Instant>, done: F) -> bool
+        where F: Fn(&Counters) -> bool {
+
+        let mut guard = self.lock.lock().unwrap();
+        self.waiting.store(true, Ordering::SeqCst);
+        let done = loop {
+            if done(self) {
+                break true;
+            }
+            match deadline {
+                None => guard = self.changed.wait(guard).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break false;
+                    }
+                    guard = self.changed.wait_timeout(guard, deadline - now).unwrap().0;
+                }
+            }
+        };
+        self.waiting.store(false, Ordering::SeqCst);
+        done
+    }
+
+    /// Wait until no task is running, returns false if the deadline
+    /// passed first.
+    pub fn wait_idle(&self, deadline: Option<Instant>) -> bool {
+        self.wait_until(deadline, |c| {
+            c.finished.load(Ordering::SeqCst) == c.started.load(Ordering::SeqCst)
+        })
+    }
+
+    /// Wait until every task that was added finished, returns false
+    /// if the deadline passed first.
+    pub fn wait_empty(&self, deadline: Option<Instant>) -> bool {
+        self.wait_until(deadline, |c| {
+            c.finished.load(Ordering::SeqCst) == c.added.load(Ordering::SeqCst)
+        })
+    }
+
+    pub fn report(&self, timed_out: bool) -> ShutdownReport {
+        // loaded in this order each value is at least the previous one
+        let finished = self.finished.load(Ordering::SeqCst);
+        let started = self.started.load(Ordering::SeqCst);
+        let added = self.added.load(Ordering::SeqCst);
+        ShutdownReport {
+            completed: finished,
+            dropped: added - started,
+            running: started - finished,
+            timed_out: timed_out
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Tracking of the tasks that finish after their deadline.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Callback invoked with how late a task finished.
+pub type DeadlineMissFn = Arc<Fn(Duration) + Send + Sync>;
+
+pub struct Deadlines {
+    misses: AtomicUsize,
+    callback: Option<DeadlineMissFn>
+}
+
+impl Deadlines {
+    pub fn new(callback: Option<DeadlineMissFn>) -> Arc<Deadlines> {
+        Arc::new(Deadlines {
+            misses: AtomicUsize::new(0),
+            callback: callback
+        })
+    }
+
+    /// Report the task that just finished if it is past `deadline`.
+    pub fn finished(&self, deadline: Instant) {
+        let now = Instant::now();
+        if now > deadline {
+            self.misses.fetch_add(1, Ordering::SeqCst);
+            if let Some(ref callback) = self.callback {
+                callback(now - deadline);
+            }
+        }
+    }
+
+    /// The number of tasks that finished after their deadline.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::SeqCst)
+    }
+}
@@ -2,165 +2,514 @@
 //! on a separate thread. All it does is listening to a command
 //! channel and starting new tasks when the time comes.
 
+use std::cmp;
 use std::sync::atomic::*;
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{Sender, Receiver, channel};
-use std::collections::HashMap;
+use std::sync::{Arc, Weak, Mutex};
+use std::sync::mpsc::{Sender, Receiver, SendError, channel};
+use std::collections::{HashMap, HashSet, BinaryHeap, VecDeque};
 use std::thread;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
 
 use bran;
 use pulse::*;
-use deque;
-use num_cpus;
 
-use {Wait, Schedule, FnBox};
+use {Wait, Placement, WorkerId, TaskOptions};
+use {FrontendBuilder, Scheduling, ShutdownReport, StealStats};
+use topology::{self, Topology};
+use pool::{Pool, Run};
+use job::Job;
+use affinity;
+use waiting;
 use super::worker;
+use super::injector::Injector;
+use super::queue::{self, Queue, Victim};
 
 struct Inner {
-    index: usize,
-    stealers: HashMap<usize, deque::Stealer<ReadyTask>>,
+    stealers: HashMap<usize, Victim>,
     workers: HashMap<usize, Sender<worker::Command>>,
-    joins: Vec<thread::JoinHandle<()>>
+    pinned: HashMap<usize, Sender<ReadyTask>>,
+    /// The workers tasks were pinned to, they don't retire when the
+    /// pool scales down
+    has_pinned: HashSet<usize>,
+    /// The core each worker that is not pinned started on
+    cores: HashMap<usize, usize>,
+    joins: Vec<thread::JoinHandle<()>>,
+    /// The threads of the tasks that need a large stack
+    large: Vec<thread::JoinHandle<()>>
 }
 
+/// The tasks that need a large stack and wait for a thread
+struct LargeTasks {
+    /// The number of threads running them
+    threads: usize,
+    /// The tasks and their stack sizes, in the order they were ready
+    queue: VecDeque<(Job, usize)>
+}
+
+/// The pools that are alive, a thread that is not a worker helps them
+/// while waiting
+static POOLS: Mutex<Vec<Weak<Backend>>> = Mutex::new(Vec::new());
+
 /// Task queue back-end.
 pub struct Backend {
+    name: String,
     active: AtomicBool,
-    global_queue: Mutex<deque::Worker<ReadyTask>>,
+    injector: Injector<ReadyTask>,
     workers: Mutex<Inner>,
-    pool: bran::StackPool
+    pool: Pool,
+    scheduling: Scheduling,
+    deadline_queue: Mutex<BinaryHeap<ByDeadline>>,
+    deadline_len: AtomicUsize,
+    worker_count: AtomicUsize,
+    scale_min: AtomicUsize,
+    scale_max: AtomicUsize,
+    growing: AtomicBool,
+    affinity: Option<Vec<usize>>,
+    topology: Topology,
+    local_steals: AtomicUsize,
+    remote_steals: AtomicUsize,
+    stack_size: Option<usize>,
+    stacks: bran::StackPool,
+    large_tasks: Mutex<LargeTasks>,
+    /// The most threads running tasks that need a large stack
+    large_max: usize
 }
 
 /// A ready task
-pub struct ReadyTask(bran::Handle);
+pub struct ReadyTask {
+    body: Body,
+    /// The worker the task is pinned to, if any
+    worker: Option<usize>,
+    /// When the task should be finished
+    deadline: Option<Instant>
+}
+
+/// What a ready task runs
+enum Body {
+    /// A fiber, which may suspend
+    Fiber(bran::Handle),
+    /// A task that does not suspend, it runs on the worker's stack
+    Direct(Job, Weak<Backend>)
+}
 
 impl ReadyTask {
-    pub fn run(self) {
+    /// Run the task on this thread, if it suspends it is resumed on
+    /// a worker of `back`, the pool it belongs to.
+    pub fn run(self, back: &Arc<Backend>) {
         use bran::fiber::State;
-        let ReadyTask(task) = self;
-        match task.run() {
-            State::Pending(signal) => {
-                worker::requeue(task, signal);
-            }
-            State::PendingTimeout(_, _) => {
-                panic!("Timeouts are not supported")
+        let ReadyTask{body, worker, deadline} = self;
+        match body {
+            Body::Fiber(fiber) => match worker::enter_fiber(|| fiber.run()) {
+                State::Pending(signal) => {
+                    let task = ReadyTask{
+                        body: Body::Fiber(fiber),
+                        worker: worker,
+                        deadline: deadline
+                    };
+                    Backend::enqueue(back.clone(), task, signal);
+                }
+                State::PendingTimeout(_, _) => {
+                    panic!("Timeouts are not supported")
+                }
+                State::Finished | State::Panicked => ()
+            },
+            Body::Direct(job, sched) => {
+                // a panic is contained to the task, as it is in a fiber
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                    job.run(&mut worker::FiberSchedule(sched))
+                }));
             }
-            State::Finished | State::Panicked => ()
         }
     }
 }
 
-impl Backend {
-    /// Create a new back-end.
-    pub fn new() -> Arc<Backend> {
-        let buffer = deque::BufferPool::new();
-        let (worker, stealer) = buffer.deque();
+/// Orders the tasks in the deadline queue, earliest first
+struct ByDeadline(Instant, ReadyTask);
+
+impl Ord for ByDeadline {
+    fn cmp(&self, other: &ByDeadline) -> cmp::Ordering {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl PartialOrd for ByDeadline {
+    fn partial_cmp(&self, other: &ByDeadline) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ByDeadline {
+    fn eq(&self, other: &ByDeadline) -> bool {
+        self.0 == other.0
+    }
+}
 
-        let mut map = HashMap::new();
-        map.insert(0, stealer);
+impl Eq for ByDeadline {}
 
+impl Backend {
+    /// Create a new back-end.
+    pub fn new(config: &FrontendBuilder) -> Arc<Backend> {
         let back = Arc::new(Backend {
+            name: config.name.clone(),
             active: AtomicBool::new(false),
-            global_queue: Mutex::new(worker),
+            injector: Injector::new(),
             workers: Mutex::new(Inner {
-                index: 1,
-                stealers: map,
+                stealers: HashMap::new(),
                 workers: HashMap::new(),
-                joins: Vec::new()
+                pinned: HashMap::new(),
+                has_pinned: HashSet::new(),
+                cores: HashMap::new(),
+                joins: Vec::new(),
+                large: Vec::new()
             }),
-            pool: bran::StackPool::new()
+            pool: Pool::new(config),
+            scheduling: config.scheduling,
+            deadline_queue: Mutex::new(BinaryHeap::new()),
+            deadline_len: AtomicUsize::new(0),
+            worker_count: AtomicUsize::new(0),
+            scale_min: AtomicUsize::new(0),
+            scale_max: AtomicUsize::new(0),
+            growing: AtomicBool::new(false),
+            affinity: config.affinity.clone(),
+            topology: Topology::read(),
+            local_steals: AtomicUsize::new(0),
+            remote_steals: AtomicUsize::new(0),
+            stack_size: config.stack_size,
+            stacks: bran::StackPool::new(),
+            large_tasks: Mutex::new(LargeTasks {
+                threads: 0,
+                queue: VecDeque::new()
+            }),
+            large_max: config.workers
         });
 
-        for _ in 0..num_cpus::get() {
+        for _ in 0..config.workers {
             worker::Worker::new(back.clone()).start();
         }
+        let mut pools = POOLS.lock().unwrap();
+        pools.retain(|pool| pool.upgrade().is_some());
+        pools.push(Arc::downgrade(&back));
         back
     }
 
     /// Start a task on the global work queue
-    fn start_on_global_queue(&self, rt: ReadyTask) {
-        let guard = self.global_queue.lock().unwrap();
-        guard.push(rt);
-    }
-
-    /// Start a task that will run once all the Handle's have
-    /// been completed.
-    pub fn start(back: Arc<Backend>, task: Box<FnBox+Send>, mut after: Vec<Signal>) {
-        // Create the wait signal if needed
-        let signal = if after.len() == 0 {
-            Signal::pulsed()
-        } else if after.len() == 1 {
-            after.pop().unwrap()
-        } else {
-            Barrier::new(&after).signal()
-        };
+    pub fn start_on_global_queue(&self, rt: ReadyTask) {
+        self.injector.push(rt);
+    }
 
-        signal.callback(move || {
-            if !back.active.load(Ordering::SeqCst) {
-                let fiber = bran::fiber::Fiber::spawn_with(move || {
-                    task.call_box(&mut worker::FiberSchedule)
-                }, back.pool.clone());
-                let try_thread = worker::start(ReadyTask(fiber));
-                match try_thread {
-                    Ok(b) => b,
-                    Err(rt) => {
-                        back.start_on_global_queue(rt);
-                        true
-                    }
-                };
-            }
-        });
+    /// Take all the tasks of the global work queue, the oldest first
+    pub fn take_global_queue(&self) -> Vec<ReadyTask> {
+        self.injector.take_all()
     }
 
-    /// Start a task that will run once all the Handle's have
-    /// been completed.
-    pub fn enqueue(back: Arc<Backend>, task: bran::Handle, after: Signal) {
-        after.callback(move || {
-            if !back.active.load(Ordering::SeqCst) {
-                let try_thread = worker::start(ReadyTask(task));
-                match try_thread {
-                    Ok(b) => b,
-                    Err(rt) => {
-                        back.start_on_global_queue(rt);
-                        true
-                    }
+    /// Push a ready task to the queue of the worker it is pinned to,
+    /// or to the local queue if it can run anywhere. A task that was
+    /// `woken` goes to the next task slot of the worker.
+    fn dispatch(&self, rt: ReadyTask, woken: bool) {
+        let rt = match rt.worker {
+            Some(index) => {
+                let guard = self.workers.lock().unwrap();
+                let rt = match guard.pinned.get(&index) {
+                    Some(send) => match send.send(rt) {
+                        Ok(()) => return,
+                        Err(SendError(rt)) => rt
+                    },
+                    None => rt
                 };
+                warn!("Worker {} is gone, running its pinned task elsewhere", index);
+                rt
             }
-        });
+            None => rt
+        };
+
+        if let (Scheduling::EarliestDeadline, Some(deadline)) = (self.scheduling, rt.deadline) {
+            let mut queue = self.deadline_queue.lock().unwrap();
+            queue.push(ByDeadline(deadline, rt));
+            self.deadline_len.store(queue.len(), Ordering::SeqCst);
+            return;
+        }
+
+        if let Err(rt) = worker::start(self, rt, woken) {
+            self.start_on_global_queue(rt);
+        }
     }
 
-    /// Kill the backend, wait until the condition is satisfied.
-    pub fn exit(&self, wait: Wait) {
-        // read the current active count, OR in the BLOCK
-        // flag if needed for the wait
-        match wait {
-            Wait::None | Wait::Active => {
-                self.active.store(true, Ordering::SeqCst);
+    /// Resume a suspended task once `after` is asserted. This is
+    /// done even while shutting down, the task already started and
+    /// `Wait::Active` waits for it.
+    pub fn enqueue(back: Arc<Backend>, task: ReadyTask, after: Signal) {
+        after.callback(move || back.dispatch(task, true));
+    }
+
+    /// Change the number of workers. Retiring workers hand their
+    /// queued tasks over to the remaining ones, the workers tasks were
+    /// pinned to are removed last.
+    pub fn set_worker_count(back: &Arc<Backend>, count: usize) {
+        assert!(count > 0, "a pool needs at least one worker");
+        let current = {
+            let mut guard = back.workers.lock().unwrap();
+            let mut indices: Vec<usize> = guard.workers.keys().cloned().collect();
+            // the last ones are removed first
+            indices.sort_by_key(|index| (!guard.has_pinned.contains(index), *index));
+            while indices.len() > count {
+                let index = indices.pop().unwrap();
+                if let Some(send) = back.remove_worker(&mut guard, index) {
+                    let _ = send.send(worker::Command::Retire);
+                }
             }
-            Wait::Pending => ()
+            indices.len()
         };
 
+        for _ in current..count {
+            worker::Worker::new(back.clone()).start();
+        }
+    }
+
+    /// Let the pool scale between `min` and `max` workers depending
+    /// on how many ready tasks are waiting for a worker.
+    pub fn set_auto_scaling(&self, range: Option<(usize, usize)>) {
+        // zero disables the scaling
+        let (min, max) = range.unwrap_or((0, 0));
+        if range.is_some() {
+            assert!(0 < min && min <= max, "invalid worker range");
+        }
+        self.scale_max.store(0, Ordering::SeqCst);
+        self.scale_min.store(min, Ordering::SeqCst);
+        self.scale_max.store(max, Ordering::SeqCst);
+    }
+
+    /// Add a worker if the ready tasks are piling up.
+    fn scale_up(back: &Arc<Backend>) {
+        let max = back.scale_max.load(Ordering::Relaxed);
+        if max == 0 {
+            return;
+        }
+        let count = back.worker_count.load(Ordering::SeqCst);
+        if count < max && back.pool.counters.queued() > 2 * count &&
+           !back.growing.swap(true, Ordering::SeqCst) {
+            worker::Worker::new(back.clone()).start();
+            back.growing.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Called by an idle worker, it retires if there is more workers
+    /// than needed. A worker tasks were pinned to never retires, the
+    /// tasks pinned to it later would have nowhere to run.
+    pub fn try_retire(&self, index: usize) -> bool {
+        let min = self.scale_min.load(Ordering::SeqCst);
+        if min == 0 {
+            return false;
+        }
+        let mut guard = self.workers.lock().unwrap();
+        if guard.workers.len() <= min || guard.has_pinned.contains(&index) ||
+           self.pool.counters.queued() > 0 {
+            return false;
+        }
+        self.remove_worker(&mut guard, index).is_some()
+    }
+
+    /// Remove a worker from the pool, the other workers stop stealing
+    /// from it. Returns the command channel of the removed worker.
+    fn remove_worker(&self, inner: &mut Inner, index: usize)
+        -> Option<Sender<worker::Command>> {
+
+        let send = inner.workers.remove(&index);
+        if send.is_some() {
+            inner.stealers.remove(&index);
+            inner.pinned.remove(&index);
+            inner.has_pinned.remove(&index);
+            inner.cores.remove(&index);
+            for (_, other) in inner.workers.iter() {
+                let _ = other.send(worker::Command::Remove(index));
+            }
+            self.worker_count.fetch_sub(1, Ordering::SeqCst);
+        }
+        send
+    }
+
+    /// The core worker `index` is pinned to, if any.
+    pub fn pinned_core(&self, index: usize) -> Option<usize> {
+        // worker indices start at one
+        self.affinity.as_ref().map(|cores| cores[(index - 1) % cores.len()])
+    }
+
+    /// The core worker `index` runs on, if known. A worker that is not
+    /// pinned may be moved by the OS, its core is the one it started on.
+    fn core(&self, index: usize) -> Option<usize> {
+        self.pinned_core(index).or_else(|| {
+            self.workers.lock().unwrap().cores.get(&index).cloned()
+        })
+    }
+
+    /// Record the core a worker that is not pinned started on. The
+    /// other workers are told again about its queue, to sort it by its
+    /// distance to them.
+    pub fn set_core(&self, index: usize, core: usize) {
         let mut guard = self.workers.lock().unwrap();
-        for (_, send) in guard.workers.iter() {
-            let _ = send.send(worker::Command::Exit);
+        guard.cores.insert(index, core);
+        if let Some(stealer) = guard.stealers.get(&index) {
+            for (&other, send) in guard.workers.iter() {
+                if other != index {
+                    let _ = send.send(worker::Command::Add(index, stealer.clone()));
+                }
+            }
         }
+    }
 
-        while let Some(join) = guard.joins.pop() {
-            join.join().unwrap();
+    /// How far the queue `victim` is from worker `index`.
+    pub fn tier(&self, index: usize, victim: usize) -> usize {
+        match (self.core(index), self.core(victim)) {
+            (Some(a), Some(b)) => self.topology.tier(a, b),
+            _ => topology::REMOTE
         }
     }
 
+    /// Count a steal from a worker at the given tier.
+    pub fn count_steal(&self, tier: usize) {
+        if tier == topology::REMOTE {
+            self.remote_steals.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.local_steals.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The number of local and remote steals so far.
+    pub fn steal_stats(&self) -> StealStats {
+        StealStats {
+            local: self.local_steals.load(Ordering::Relaxed),
+            remote: self.remote_steals.load(Ordering::Relaxed)
+        }
+    }
+
+    /// The name of the pool, used to name the worker threads.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A builder for a worker thread.
+    pub fn thread(&self, name: String) -> thread::Builder {
+        let builder = thread::Builder::new().name(name);
+        match self.stack_size {
+            Some(size) => builder.stack_size(size),
+            None => builder
+        }
+    }
+
+    /// Take the ready task with the earliest deadline, if any.
+    pub fn pop_deadline(&self) -> Option<ReadyTask> {
+        if self.deadline_len.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+
+        let mut queue = self.deadline_queue.lock().unwrap();
+        let task = queue.pop();
+        self.deadline_len.store(queue.len(), Ordering::SeqCst);
+        task.map(|ByDeadline(_, task)| task)
+    }
+
+    /// Run ready tasks on this thread until `signal` is asserted, on a
+    /// worker this is the same as `fibe::wait`.
+    pub fn help(back: &Arc<Backend>, signal: &Signal) {
+        if worker::on_worker() {
+            let _ = ::wait(signal.clone());
+            return;
+        }
+
+        waiting::unpark_on(signal);
+        while signal.is_pending() {
+            if !Backend::help_one(back) && !back.pool.main.run_one(&mut back.clone()) {
+                thread::park_timeout(waiting::IDLE);
+            }
+        }
+    }
+
+    /// Run a task of the deadline queue or the global queue on this
+    /// thread, which is not a worker. Returns false if there was none.
+    pub fn help_one(back: &Arc<Backend>) -> bool {
+        // the other tasks stay where the workers can take them
+        let task = back.pop_deadline().or_else(|| back.injector.pop());
+        match task {
+            Some(task) => {
+                task.run(back);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Run a task of one of the pools like `help_one`. Returns false if
+    /// none of them had one.
+    pub fn help_any() -> bool {
+        let pools: Vec<Arc<Backend>> = POOLS.lock().unwrap().iter()
+            .filter_map(|pool| pool.upgrade())
+            .collect();
+        pools.iter().any(|back| Backend::help_one(back))
+    }
+
+    /// List the workers tasks can be pinned to.
+    pub fn workers(&self) -> Vec<WorkerId> {
+        let guard = self.workers.lock().unwrap();
+        let mut workers: Vec<WorkerId> =
+            guard.workers.keys().map(|&index| WorkerId(index)).collect();
+        workers.sort_by(|a, b| a.0.cmp(&b.0));
+        workers
+    }
+
+    /// Kill the backend, wait until the condition is satisfied.
+    pub fn exit(back: &Arc<Backend>, wait: Wait, timeout: Option<Duration>) -> ShutdownReport {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut timed_out = false;
+
+        // the workers must keep running until the tasks that are
+        // waited for are done, a worker only exits once it is idle
+        if wait == Wait::Pending {
+            timed_out = !Pool::flush(back, deadline);
+        }
+        back.active.store(true, Ordering::SeqCst);
+        if wait != Wait::None && !back.pool.counters.wait_idle(deadline) {
+            timed_out = true;
+        }
+
+        let joins = {
+            let mut guard = back.workers.lock().unwrap();
+            for (_, send) in guard.workers.iter() {
+                let _ = send.send(worker::Command::Exit);
+            }
+            let mut joins = mem::replace(&mut guard.joins, Vec::new());
+            joins.extend(guard.large.drain(..));
+            joins
+        };
+
+        // a worker stuck in a task that outlived the timeout
+        // is left behind
+        if !timed_out {
+            // the last handle may be dropped by a task, then this runs
+            // on a worker, which exits once the task is done
+            let current = thread::current().id();
+            for join in joins {
+                if join.thread().id() != current {
+                    join.join().unwrap();
+                }
+            }
+        }
+        back.pool.counters.report(timed_out)
+    }
+
     /// Create a new deque
     pub fn new_deque(&self) -> (usize,
-                                deque::Worker<ReadyTask>,
-                                Receiver<worker::Command>) {
+                                Queue,
+                                Receiver<worker::Command>,
+                                Receiver<ReadyTask>) {
 
-        let buffer = deque::BufferPool::new();
-        let (worker, stealer) = buffer.deque();
+        let (worker, stealer) = queue::new();
         let (send, recv) = channel();
+        let (pinned_send, pinned_recv) = channel();
         let mut guard = self.workers.lock().unwrap();
-        let index = guard.index;
-        guard.index += 1;
+        // the indices of the removed workers are reused, so that the
+        // workers keep the cores of `FrontendBuilder::affinity` apart
+        let index = (1..).find(|index| !guard.workers.contains_key(index)).unwrap();
         for (&key, stealer) in guard.stealers.iter() {
             send.send(worker::Command::Add(key, stealer.clone())).unwrap();
         }
@@ -169,7 +518,77 @@ impl Backend {
         }
         guard.stealers.insert(index, stealer);
         guard.workers.insert(index, send);
-        (index, worker, recv)
+        guard.pinned.insert(index, pinned_send);
+        self.worker_count.fetch_add(1, Ordering::SeqCst);
+        (index, worker, recv, pinned_recv)
+    }
+
+    /// Run a task that needs a large stack on a thread of its own. The
+    /// fiber stacks come from bran's pool, which can't be sized, while
+    /// the end of a thread's stack is guarded. There are at most as
+    /// many of these threads as the pool started with workers, the other
+    /// tasks wait for one of them.
+    fn spawn_large(back: &Arc<Backend>, task: Job, size: usize) {
+        let size = cmp::max(size, back.stack_size.unwrap_or(0));
+        {
+            let mut large = back.large_tasks.lock().unwrap();
+            if large.threads >= back.large_max {
+                large.queue.push_back((task, size));
+                return;
+            }
+            large.threads += 1;
+        }
+        Backend::large_thread(back, task, size)
+    }
+
+    /// Start a thread with a stack of `size` bytes, it runs `task` and
+    /// then the queued tasks, until one needs a larger stack. The
+    /// thread is joined on exit like a worker.
+    fn large_thread(back: &Arc<Backend>, task: Job, size: usize) {
+        let weak = Arc::downgrade(back);
+        let cores = back.affinity.clone();
+        let name = format!("{} stack", back.name);
+        let join = thread::Builder::new().name(name).stack_size(size).spawn(move || {
+            // the tasks are not pinned, they may run on any of the cores
+            if let Some(ref cores) = cores {
+                affinity::pin_current_thread(cores);
+            }
+            let mut task = task;
+            loop {
+                // a panic is contained to the task, as it is in a fiber
+                let sched = weak.clone();
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                    task.run(&mut worker::FiberSchedule(sched))
+                }));
+
+                let back = match weak.upgrade() {
+                    Some(back) => back,
+                    None => return
+                };
+                let next = {
+                    let mut large = back.large_tasks.lock().unwrap();
+                    if back.active.load(Ordering::SeqCst) {
+                        // shutting down, the queued tasks won't start
+                        large.queue.clear();
+                    }
+                    let next = large.queue.pop_front();
+                    if next.is_none() {
+                        large.threads -= 1;
+                    }
+                    next
+                };
+                match next {
+                    Some((next, needs)) if needs <= size => task = next,
+                    // the new thread takes the place of this one
+                    Some((next, needs)) => return Backend::large_thread(&back, next, needs),
+                    None => return
+                }
+            }
+        }).unwrap();
+
+        let mut guard = back.workers.lock().unwrap();
+        guard.large.retain(|join| !join.is_finished());
+        guard.large.push(join);
     }
 
     ///
@@ -179,8 +598,53 @@ impl Backend {
     }
 }
 
-impl<'a> Schedule for Arc<Backend>  {
-    fn add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>) {
-        Backend::start(self.clone(), task, after)
+impl Run for Backend {
+    fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    fn pin(&self, worker: WorkerId) {
+        let mut guard = self.workers.lock().unwrap();
+        assert!(guard.workers.contains_key(&worker.0),
+                "{:?} is not a worker of the pool, it may have been removed", worker);
+        guard.has_pinned.insert(worker.0);
+    }
+
+    fn run(back: Arc<Backend>, task: Job, options: TaskOptions, woken: bool) {
+        if back.active.load(Ordering::SeqCst) {
+            return;
+        }
+        Backend::scale_up(&back);
+
+        let worker = match options.placement {
+            Placement::Any => None,
+            Placement::Worker(WorkerId(index)) => Some(index),
+            Placement::MainThread => {
+                // the main thread does not run fibers, the task
+                // runs directly on its stack
+                back.pool.main.push(task);
+                return;
+            }
+        };
+        if let Some(size) = options.stack_size {
+            match worker {
+                None => return Backend::spawn_large(&back, task, size),
+                Some(index) => warn!("Task pinned to worker {} runs on a fiber, \
+                                      its stack size is ignored", index)
+            }
+        }
+        let sched = Arc::downgrade(&back);
+        let body = if options.no_suspend {
+            Body::Direct(task, sched)
+        } else {
+            Body::Fiber(bran::fiber::Fiber::spawn_with(move || {
+                task.run(&mut worker::FiberSchedule(sched))
+            }, back.stacks.clone()))
+        };
+        back.dispatch(ReadyTask{
+            body: body,
+            worker: worker,
+            deadline: options.deadline
+        }, woken);
     }
 }
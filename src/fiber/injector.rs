@@ -0,0 +1,254 @@
+//! Lock-free queue used to hand tasks to the workers from threads
+//! that don't own a deque (the main thread, signal callbacks).
+
+use std::collections::VecDeque;
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+struct Node<T> {
+    task: T,
+    next: *mut Node<T>
+}
+
+/// A multi-producer queue, producers push single tasks and consumers
+/// take either the oldest task or everything that was pushed so far.
+/// The tasks are pushed on a stack, since the whole stack is always
+/// taken at once there is no ABA problem. Taking a single task moves
+/// the stack to a list of its own, where the next ones are taken from.
+pub struct Injector<T> {
+    head: AtomicPtr<Node<T>>,
+    /// Tasks moved off the stack, the oldest first. They are older
+    /// than the ones still on the stack
+    taken: Mutex<VecDeque<T>>,
+    /// The length of `taken`, read without locking it
+    taken_len: AtomicUsize
+}
+
+// the tasks are moved between threads, the nodes are only reachable
+// by the thread that pushed them or the one that took them
+unsafe impl<T: Send> Send for Injector<T> {}
+unsafe impl<T: Send> Sync for Injector<T> {}
+
+impl<T> Injector<T> {
+    /// Create an empty queue
+    pub fn new() -> Injector<T> {
+        Injector {
+            head: AtomicPtr::new(ptr::null_mut()),
+            taken: Mutex::new(VecDeque::new()),
+            taken_len: AtomicUsize::new(0)
+        }
+    }
+
+    /// Add a task to the queue
+    pub fn push(&self, task: T) {
+        let node = Box::into_raw(Box::new(Node {
+            task: task,
+            next: ptr::null_mut()
+        }));
+
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head; }
+            match self.head.compare_exchange_weak(head, node,
+                                                  Ordering::Release,
+                                                  Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current
+            }
+        }
+    }
+
+    /// Check if the queue looks empty, without taking any cache line
+    /// for writing
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null() &&
+        self.taken_len.load(Ordering::Relaxed) == 0
+    }
+
+    /// Take the oldest task
+    pub fn pop(&self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut taken = self.taken.lock().unwrap();
+        if taken.is_empty() {
+            taken.extend(self.take_stack());
+        }
+        let task = taken.pop_front();
+        self.taken_len.store(taken.len(), Ordering::Relaxed);
+        task
+    }
+
+    /// Take all the queued tasks, the oldest first
+    pub fn take_all(&self) -> Vec<T> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let mut taken = self.taken.lock().unwrap();
+        let mut tasks: Vec<T> = taken.drain(..).collect();
+        self.taken_len.store(0, Ordering::Relaxed);
+        tasks.extend(self.take_stack());
+        tasks
+    }
+
+    /// Take the tasks on the stack, the oldest first
+    fn take_stack(&self) -> Vec<T> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut tasks = Vec::new();
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node) };
+            let Node{task, next} = *boxed;
+            tasks.push(task);
+            node = next;
+        }
+        tasks.reverse();
+        tasks
+    }
+}
+
+impl<T> Drop for Injector<T> {
+    fn drop(&mut self) {
+        self.take_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use super::Injector;
+
+    #[test]
+    fn take_all_oldest_first() {
+        let injector = Injector::new();
+        assert!(injector.is_empty());
+        for i in 0..10 {
+            injector.push(i);
+        }
+        assert!(!injector.is_empty());
+        assert_eq!(injector.take_all(), (0..10).collect::<Vec<_>>());
+        assert!(injector.is_empty());
+        assert_eq!(injector.take_all(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn pop_oldest_first() {
+        let injector = Injector::new();
+        assert_eq!(injector.pop(), None);
+        for i in 0..5 {
+            injector.push(i);
+        }
+        assert_eq!(injector.pop(), Some(0));
+        injector.push(5);
+        assert_eq!(injector.pop(), Some(1));
+        assert!(!injector.is_empty());
+
+        // the tasks left behind by pop come before the pushed ones
+        injector.push(6);
+        assert_eq!(injector.take_all(), vec![2, 3, 4, 5, 6]);
+        assert!(injector.is_empty());
+        assert_eq!(injector.pop(), None);
+    }
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn drop_drains() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        {
+            let injector = Injector::new();
+            for _ in 0..10 {
+                injector.push(Counted(dropped.clone()));
+            }
+        }
+        assert_eq!(dropped.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn many_producers() {
+        const PRODUCERS: usize = 4;
+        const TASKS: usize = 10_000;
+
+        let injector = Arc::new(Injector::new());
+        let producers: Vec<_> = (0..PRODUCERS).map(|p| {
+            let injector = injector.clone();
+            thread::spawn(move || {
+                for i in 0..TASKS {
+                    injector.push((p, i));
+                }
+            })
+        }).collect();
+
+        // take while the producers push, each producer's tasks must
+        // come out in order and exactly once
+        let mut next = vec![0; PRODUCERS];
+        let mut taken = 0;
+        while taken < PRODUCERS * TASKS {
+            for (p, i) in injector.take_all() {
+                assert_eq!(next[p], i);
+                next[p] += 1;
+                taken += 1;
+            }
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert!(injector.is_empty());
+    }
+}
+
+#[cfg(all(test, feature="unstable"))]
+mod bench {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use test::Bencher;
+    use super::Injector;
+
+    /// Push from 4 threads while this thread takes the tasks
+    fn push_from_4_threads<P, T>(b: &mut Bencher, push: P, take: T)
+        where P: Fn(usize) + Send + Sync + 'static,
+              T: Fn() -> usize {
+        let push = Arc::new(push);
+        b.iter(|| {
+            let threads: Vec<_> = (0..4).map(|_| {
+                let push = push.clone();
+                thread::spawn(move || for i in 0..250 { push(i) })
+            }).collect();
+            let mut taken = 0;
+            while taken < 1000 {
+                taken += take();
+            }
+            for thread in threads {
+                thread.join().unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn injector(b: &mut Bencher) {
+        let injector = Arc::new(Injector::new());
+        let pusher = injector.clone();
+        push_from_4_threads(b, move |i| pusher.push(i), || injector.take_all().len());
+    }
+
+    /// The mutex guarded queue the injector replaced
+    #[bench]
+    fn mutex(b: &mut Bencher) {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let pusher = queue.clone();
+        push_from_4_threads(b, move |i| pusher.lock().unwrap().push_back(i), || {
+            queue.lock().unwrap().pop_front().map(|_| 1).unwrap_or(0)
+        });
+    }
+}
@@ -1,3 +1,4 @@
 pub mod back;
-pub mod front;
+pub mod injector;
+pub mod queue;
 pub mod worker;
\ No newline at end of file
@@ -0,0 +1,86 @@
+//! The work stealing queue of a worker. It keeps an estimate of its
+//! length, so that thieves can take half of the tasks at once.
+
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use deque::{self, Stolen};
+use super::back::ReadyTask;
+
+/// The most tasks taken in a single steal
+const MAX_BATCH: usize = 32;
+
+/// The end of the queue owned by the worker
+pub struct Queue {
+    queue: deque::Worker<ReadyTask>,
+    len: Arc<AtomicUsize>
+}
+
+/// The end of the queue the other workers steal from
+#[derive(Clone)]
+pub struct Victim {
+    stealer: deque::Stealer<ReadyTask>,
+    len: Arc<AtomicUsize>
+}
+
+/// Create a new queue
+pub fn new() -> (Queue, Victim) {
+    let buffer = deque::BufferPool::new();
+    let (worker, stealer) = buffer.deque();
+    let len = Arc::new(AtomicUsize::new(0));
+    (Queue{queue: worker, len: len.clone()},
+     Victim{stealer: stealer, len: len})
+}
+
+impl Queue {
+    /// Add a task, it is the next one to be popped
+    pub fn push(&self, task: ReadyTask) {
+        // counted before it is visible, so a thief never makes the
+        // length wrap around
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.queue.push(task);
+    }
+
+    /// Take the last pushed task
+    pub fn pop(&self) -> Option<ReadyTask> {
+        let task = self.queue.pop();
+        if task.is_some() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        task
+    }
+}
+
+impl Victim {
+    /// Steal about half of the tasks, the first one is returned and
+    /// the others are pushed to `into`.
+    pub fn steal_half(&self, into: &Queue) -> Option<ReadyTask> {
+        let first = match self.steal() {
+            Some(task) => task,
+            None => return None
+        };
+
+        let half = self.len.load(Ordering::Relaxed) / 2;
+        for _ in 0..cmp::min(half, MAX_BATCH) {
+            match self.steal() {
+                Some(task) => into.push(task),
+                None => break
+            }
+        }
+        Some(first)
+    }
+
+    fn steal(&self) -> Option<ReadyTask> {
+        loop {
+            match self.stealer.steal() {
+                Stolen::Data(task) => {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    return Some(task);
+                }
+                Stolen::Abort => continue,
+                Stolen::Empty => return None
+            }
+        }
+    }
+}
@@ -1,49 +1,80 @@
 
-use std::cell::RefCell;
-use std::sync::Arc;
-use std::thread;
+use std::cell::{Cell, RefCell};
+use std::sync::{Arc, Weak};
 use std::sync::mpsc::Receiver;
-use std::thread::sleep_ms;
+use std::thread::{self, sleep_ms};
 
-use pulse::Signal;
+use pulse::{Signal, Signals};
 use rand::{self, Rng};
-use deque::{self, Stolen};
 use super::back::{Backend, ReadyTask};
-use Schedule;
-use bran;
+use super::queue::{Queue, Victim};
+use {Schedule, TaskBox, TaskOptions, Full};
+use affinity;
+use waiting;
+use topology;
 
 use FnBox;
 
 pub enum Command {
-    Add(usize, deque::Stealer<ReadyTask>),
+    Add(usize, Victim),
+    Remove(usize),
+    Retire,
     Exit
 }
 
+/// How long a worker backs off before it considers retiring, when
+/// the pool scales automatically
+const RETIRE_BACKOFF: u32 = 20;
+
+/// How many tasks in a row may run from the next task slot before
+/// the queue gets a turn
+const NEXT_LIMIT: usize = 32;
+
 thread_local!(static WORKER: RefCell<Option<Worker>> = RefCell::new(None));
 
+/// Set while a fiber is running on this thread
+thread_local!(static IN_FIBER: Cell<bool> = Cell::new(false));
+
 pub struct Worker {
     index: usize,
     back: Arc<Backend>,
-    queue: deque::Worker<ReadyTask>,
-    command: Option<Receiver<Command>>
+    queue: Queue,
+    /// The task woken last by this worker, it runs before the
+    /// queued ones while its data is still in the cache
+    next: Cell<Option<ReadyTask>>,
+    /// How many tasks ran from `next` in a row
+    streak: Cell<usize>,
+    command: Option<Receiver<Command>>,
+    pinned: Option<Receiver<ReadyTask>>
 }
 
 impl Worker {
     pub fn new(back: Arc<Backend>) -> Worker {
-        let (index, worker, rx) = back.new_deque();
+        let (index, worker, rx, pinned) = back.new_deque();
 
         Worker {
             back: back,
             index: index,
             queue: worker,
-            command: Some(rx)
+            next: Cell::new(None),
+            streak: Cell::new(0),
+            command: Some(rx),
+            pinned: Some(pinned)
         }
     }
 
     pub fn start(self) {
-        let name = format!("Worker {}", self.index);
+        let name = format!("{} {}", self.back.name(), self.index);
         let back = self.back.clone();
-        let guard = thread::Builder::new().name(name).spawn(move || {
+        let index = self.index;
+        let core = back.pinned_core(index);
+        let guard = back.thread(name).spawn(move || {
+            match core {
+                Some(core) => affinity::pin_current_thread(&[core]),
+                None => if let Some(core) = affinity::current_core() {
+                    self.back.set_core(index, core);
+                }
+            }
             WORKER.with(|worker| {
                 *worker.borrow_mut() = Some(self);
             });
@@ -52,24 +83,92 @@ impl Worker {
 
         back.register_worker(guard);
     }
+
+    /// Put `task` in the next task slot, the task it replaces is
+    /// queued where it can be stolen.
+    fn push_next(&self, task: ReadyTask) {
+        // a chain of tasks that keep making each other ready must
+        // not starve the queue. The local queue pops the last pushed
+        // task first, so the task goes to the back of the global queue
+        if self.streak.get() >= NEXT_LIMIT {
+            self.back.start_on_global_queue(task);
+            return;
+        }
+        if let Some(old) = self.next.replace(Some(task)) {
+            self.queue.push(old);
+        }
+    }
+
+    /// Take the task in the next task slot.
+    fn take_next(&self) -> Option<ReadyTask> {
+        let task = self.next.take();
+        if task.is_some() {
+            self.streak.set(self.streak.get() + 1);
+        } else {
+            self.streak.set(0);
+        }
+        task
+    }
 }
 
 #[inline(never)]
 fn work() {
     WORKER.with(|worker| {
         let cmd = worker.borrow_mut().as_mut().unwrap().command.take().unwrap();
+        let pinned = worker.borrow_mut().as_mut().unwrap().pinned.take().unwrap();
+        let back = worker.borrow().as_ref().unwrap().back.clone();
+        let index = worker.borrow().as_ref().unwrap().index;
 
         let mut rand = rand::XorShiftRng::new_unseeded();
-        let mut stealers: Vec<(usize, deque::Stealer<ReadyTask>)> = Vec::new();
+        // the victims, grouped by how far they are from this worker
+        let mut stealers: Vec<Vec<(usize, Victim)>> =
+            (0..topology::TIERS).map(|_| Vec::new()).collect();
+        let mut victims = 0;
 
         let mut i = 0;
         let mut run = true;
+        let mut retire = false;
         let mut backoff = 0;
 
         while run {
+            // Tasks pinned to this worker can't be stolen, so they
+            // go before anything else
+            if let Ok(task) = pinned.try_recv() {
+                task.run(&back);
+                i = 0;
+                backoff = 0;
+                continue;
+            }
+
+            // With earliest deadline first scheduling, tasks with a
+            // deadline are kept in a shared queue
+            if let Some(task) = back.pop_deadline() {
+                task.run(&back);
+                i = 0;
+                backoff = 0;
+                continue;
+            }
+
+            // The task that was just made ready goes first, it is
+            // likely to use the data of the task that ran before
+            if let Some(task) = worker.borrow().as_ref().unwrap().take_next() {
+                task.run(&back);
+                i = 0;
+                backoff = 0;
+                continue;
+            }
+
             // Try to grab form our own queue
             if let Some(task) = worker.borrow().as_ref().unwrap().queue.pop() {
-                task.run();
+                task.run(&back);
+                i = 0;
+                backoff = 0;
+                continue;
+            }
+
+            // Take the tasks submitted from outside of the pool
+            if let Some(task) = take_global(&back, &worker.borrow().as_ref().unwrap().queue) {
+                task.run(&back);
                 i = 0;
                 backoff = 0;
                 continue;
@@ -77,25 +176,70 @@ fn work() {
 
             while run {
                 i += 1;
+
+                if let Ok(task) = pinned.try_recv() {
+                    task.run(&back);
+                    i = 0;
+                    backoff = 0;
+                    break;
+                }
+
+                if let Some(task) = back.pop_deadline() {
+                    task.run(&back);
+                    i = 0;
+                    backoff = 0;
+                    break;
+                }
     
-                // Try to grab from one of the stealers
-                if stealers.len() > 0 {
+                if let Some(task) = take_global(&back, &worker.borrow().as_ref().unwrap().queue) {
+                    task.run(&back);
+                    i = 0;
+                    backoff = 0;
+                    break;
+                }
+
+                // Try to grab from one of the stealers, the closest first
+                let mut stolen = None;
+                for (tier, near) in stealers.iter().enumerate() {
+                    if near.len() == 0 {
+                        continue;
+                    }
                     let x: usize = rand.gen();
-                    let x = x % stealers.len();
-                    if let Stolen::Data(task) = stealers[x].1.steal() {
-                        task.run();
-                        i = 0;
-                        backoff = 0;
+                    let (_, ref victim) = near[x % near.len()];
+                    if let Some(task) = victim.steal_half(&worker.borrow().as_ref().unwrap().queue) {
+                        stolen = Some((tier, task));
                         break;
                     }
                 }
+                if let Some((tier, task)) = stolen {
+                    back.count_steal(tier);
+                    task.run(&back);
+                    i = 0;
+                    backoff = 0;
+                    break;
+                }
 
                 // Try to go to sleep
-                if i >= stealers.len() * 2 {
+                if i >= victims * 2 {
                     while let Ok(msg) = cmd.try_recv() {
                         match msg {
                             Command::Add(key, value) => {
-                                stealers.push((key, value));
+                                // a worker is added again once its core is known
+                                for near in stealers.iter_mut() {
+                                    near.retain(|&(k, _)| k != key);
+                                }
+                                stealers[back.tier(index, key)].push((key, value));
+                                victims = stealers.iter().map(|near| near.len()).sum();
+                            }
+                            Command::Remove(key) => {
+                                for near in stealers.iter_mut() {
+                                    near.retain(|&(k, _)| k != key);
+                                }
+                                victims = stealers.iter().map(|near| near.len()).sum();
+                            }
+                            Command::Retire => {
+                                retire = true;
+                                run = false;
                             }
                             Command::Exit => {
                                 run = false;
@@ -107,48 +251,169 @@ fn work() {
                     if i != 0 {
                         backoff += 1;
                         sleep_ms(backoff);
-                        i = stealers.len();
+                        i = victims;
+
+                        if backoff >= RETIRE_BACKOFF && back.try_retire(index) {
+                            retire = true;
+                            run = false;
+                        }
                     }
                 }
             }
         }
+
+        // hand the remaining tasks over to the rest of the pool,
+        // the worker was already removed so nothing new comes in
+        if retire {
+            while let Ok(task) = pinned.try_recv() {
+                warn!("Worker {} retired, running its pinned task elsewhere", index);
+                back.start_on_global_queue(task);
+            }
+            if let Some(task) = worker.borrow().as_ref().unwrap().next.take() {
+                back.start_on_global_queue(task);
+            }
+            while let Some(task) = worker.borrow().as_ref().unwrap().queue.pop() {
+                back.start_on_global_queue(task);
+            }
+        }
     });
 }
 
-// Use the task on the TLS queue or the queue in the backend
+/// Drain the global queue of the back-end into the worker's queue,
+/// where the other workers can steal the tasks, and return the
+/// oldest one.
+fn take_global(back: &Backend, queue: &Queue) -> Option<ReadyTask> {
+    let mut tasks = back.take_global_queue().into_iter();
+    let first = tasks.next();
+    // the queue pops the last pushed task first
+    for task in tasks.rev() {
+        queue.push(task);
+    }
+    first
+}
+
+/// Run `f`, which resumes a fiber. Waiting in it suspends the fiber
+/// rather than helping.
+pub fn enter_fiber<T, F: FnOnce() -> T>(f: F) -> T {
+    let outer = IN_FIBER.with(|in_fiber| in_fiber.replace(true));
+    let result = f();
+    IN_FIBER.with(|in_fiber| in_fiber.set(outer));
+    result
+}
+
+/// Check if this thread is a worker.
+pub fn on_worker() -> bool {
+    WORKER.with(|worker| worker.borrow().is_some())
+}
+
+/// Run the tasks of this worker until `signal` is asserted. Waiting
+/// would block the worker, and the task waited for may be queued on
+/// it. A thread that isn't a worker runs the tasks submitted to the
+/// pools from outside instead. Does nothing in a fiber.
+pub fn help(signal: &Signal) {
+    if IN_FIBER.with(|in_fiber| in_fiber.get()) {
+        return;
+    }
+    let back = match WORKER.with(|worker| worker.borrow().as_ref().map(|w| w.back.clone())) {
+        Some(back) => back,
+        None => return help_external(signal)
+    };
+
+    waiting::unpark_on(signal);
+    while signal.is_pending() {
+        match next_task(&back) {
+            Some(task) => task.run(&back),
+            None => thread::park_timeout(waiting::IDLE)
+        }
+    }
+}
+
+/// Run the tasks that were submitted to the pools from outside until
+/// `signal` is asserted, on a thread that is not a worker.
+fn help_external(signal: &Signal) {
+    waiting::unpark_on(signal);
+    while signal.is_pending() {
+        if !Backend::help_any() {
+            thread::park_timeout(waiting::IDLE);
+        }
+    }
+}
+
+/// The next task of this worker, without stealing.
+fn next_task(back: &Backend) -> Option<ReadyTask> {
+    WORKER.with(|worker| {
+        let worker = worker.borrow();
+        let worker = worker.as_ref().unwrap();
+        worker.take_next()
+              .or_else(|| worker.queue.pop())
+              .or_else(|| back.pop_deadline())
+              .or_else(|| take_global(back, &worker.queue))
+    })
+}
+
+// Use the task on the TLS queue or the queue in the backend. Only a
+// task that was woken or resumed goes to the next task slot, a task
+// spawned by a running one goes to the queue, where the idle workers
+// can steal it while its parent keeps running
 #[inline]
-pub fn start(rt: ReadyTask) -> Result<bool, ReadyTask> {
+pub fn start(back: &Backend, rt: ReadyTask, next: bool) -> Result<(), ReadyTask> {
     WORKER.with(|worker| {
-        if let Some(worker) = worker.borrow().as_ref() {
-            worker.queue.push(rt);
-            Ok(true)
-        } else {
-            Err(rt)
+        match worker.borrow().as_ref() {
+            // the signal may be asserted by a worker of another
+            // pool, which must not take the task
+            Some(worker) if &*worker.back as *const Backend == back as *const Backend => {
+                if next {
+                    worker.push_next(rt);
+                } else {
+                    worker.queue.push(rt);
+                }
+                Ok(())
+            }
+            _ => Err(rt)
         }
     })
 }
 
-/// used for fibers to give them child task spawning
-pub struct FiberSchedule;
+/// used for fibers to give them child task spawning, on the
+/// pool the fiber belongs to
+pub struct FiberSchedule(pub Weak<Backend>);
+
+impl FiberSchedule {
+    fn backend(&self) -> Arc<Backend> {
+        self.0.upgrade().expect("the back-end of a running fiber was dropped")
+    }
+}
 
 impl Schedule for FiberSchedule {
     fn add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>) {
-        let back = WORKER.with(|worker| {
-            worker.borrow()
-                  .as_ref()
-                  .expect("a fiber was resumed outside of a worker")
-                  .back.clone()
-        });
-        Backend::start(back, task, after)
+        self.backend().add_task(task, after)
     }
-}
 
-pub fn requeue(task: bran::Handle, after: Signal) {
-    let back = WORKER.with(|worker| {
-        worker.borrow()
-              .as_ref()
-              .expect("a fiber was resumed outside of a worker")
-              .back.clone()
-    });
-    Backend::enqueue(back, task, after)
-}
\ No newline at end of file
+    fn add_task_with(&mut self,
+                     task: Box<FnBox+Send>,
+                     after: Vec<Signal>,
+                     options: TaskOptions) {
+        self.backend().add_task_with(task, after, options)
+    }
+
+    fn try_add_task_with(&mut self,
+                         task: Box<FnBox+Send>,
+                         after: Vec<Signal>,
+                         options: TaskOptions) -> Result<(), Full> {
+        self.backend().try_add_task_with(task, after, options)
+    }
+
+    fn add_task_box(&mut self,
+                    task: TaskBox,
+                    after: Vec<Signal>,
+                    options: TaskOptions) {
+        self.backend().add_task_box(task, after, options)
+    }
+
+    fn try_add_task_box(&mut self,
+                        task: TaskBox,
+                        after: Vec<Signal>,
+                        options: TaskOptions) -> Result<(), Full> {
+        self.backend().try_add_task_box(task, after, options)
+    }
+}
@@ -0,0 +1,110 @@
+//! Frame pipelining. Each frame is a group of tasks, the pipeline
+//! tracks when each frame completes and bounds the number of frames
+//! that are in flight at the same time.
+
+use std::collections::VecDeque;
+
+use pulse::{Signal, Signals, Barrier};
+use future_pulse::Future;
+
+use {Schedule, TaskBuilder};
+
+/// Starts frames, keeping at most a fixed number in flight. A frame
+/// does not wait for the previous one, so the simulation of a frame
+/// can overlap the rendering of the previous one. Tasks that must not
+/// overlap can wait on `Frame::previous`.
+pub struct FramePipeline {
+    max_in_flight: usize,
+    in_flight: VecDeque<Signal>,
+    last: Option<Signal>,
+    index: u64
+}
+
+/// A frame that is being built.
+pub struct Frame {
+    index: u64,
+    previous: Signal,
+    signals: Vec<Signal>
+}
+
+impl FramePipeline {
+    /// Create a pipeline allowing `max_in_flight` frames to be
+    /// in flight at the same time.
+    pub fn new(max_in_flight: usize) -> FramePipeline {
+        assert!(max_in_flight > 0, "at least one frame must be in flight");
+        FramePipeline {
+            max_in_flight: max_in_flight,
+            in_flight: VecDeque::new(),
+            last: None,
+            index: 0
+        }
+    }
+
+    /// Begin a new frame, this blocks until there is less than
+    /// `max_in_flight` frames in flight.
+    pub fn begin_frame(&mut self) -> Frame {
+        while self.in_flight.front().map(|s| !s.is_pending()).unwrap_or(false) {
+            self.in_flight.pop_front();
+        }
+        while self.in_flight.len() >= self.max_in_flight {
+            let _ = self.in_flight.pop_front().unwrap().wait();
+        }
+
+        let index = self.index;
+        self.index += 1;
+        Frame {
+            index: index,
+            previous: self.last.clone().unwrap_or_else(Signal::pulsed),
+            signals: Vec::new()
+        }
+    }
+
+    /// Finish building the frame. The returned signal is asserted
+    /// once all of the frame's tasks completed.
+    pub fn end_frame(&mut self, frame: Frame) -> Signal {
+        let done = if frame.signals.len() == 0 {
+            Signal::pulsed()
+        } else {
+            Barrier::new(&frame.signals).signal()
+        };
+        self.in_flight.push_back(done.clone());
+        self.last = Some(done.clone());
+        done
+    }
+
+    /// The number of frames that have not completed yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.iter().filter(|s| s.is_pending()).count()
+    }
+
+    /// Block until all the frames in flight are completed.
+    pub fn wait_all(&mut self) {
+        while let Some(signal) = self.in_flight.pop_front() {
+            let _ = signal.wait();
+        }
+    }
+}
+
+impl Frame {
+    /// The number of the frame, starting at zero.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// A signal asserted once the previous frame completed.
+    pub fn previous(&self) -> Signal {
+        self.previous.clone()
+    }
+
+    /// Start a task as part of the frame.
+    pub fn start<T>(&mut self, task: TaskBuilder<T>, sched: &mut Schedule) -> Future<T> {
+        let result = task.start(sched);
+        self.signals.push(result.signal());
+        result
+    }
+
+    /// Make the frame complete only after `signal` is asserted.
+    pub fn track(&mut self, signal: Signal) {
+        self.signals.push(signal);
+    }
+}
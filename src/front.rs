@@ -0,0 +1,265 @@
+//! Front-end module for the task queue. The front-end exists
+//! on the user side, allowing to add more tasks to the queue.
+
+use std::sync::Arc;
+use std::time::Duration;
+use pulse::Signal;
+use future_pulse::Future;
+
+#[cfg(feature="fiber")]
+use fiber::back::Backend;
+#[cfg(feature="thread")]
+use thread::back::Backend;
+use pool::{Pool, Run};
+use {Wait, Schedule, FnBox, TaskBox, WorkerId, TaskOptions, FrontendBuilder, Full};
+use {ShutdownReport, Shutdown, StealStats};
+use {Strand, Resource, FramePipeline, Periodic};
+use timer;
+
+/// Queue front-end.
+pub struct Frontend {
+    backend: Arc<Backend>,
+    owner: Arc<Owner>,
+    shutdown: Shutdown
+}
+
+/// A cheap handle to start tasks on the pool of a `Frontend`, it can
+/// be cloned and sent to other threads. See `Shutdown` for how it
+/// affects the lifetime of the pool.
+#[derive(Clone)]
+pub struct Spawner {
+    backend: Arc<Backend>,
+    _owner: Option<Arc<Owner>>
+}
+
+/// Stops the back-end once the last handle keeping it running
+/// is dropped.
+struct Owner(Arc<Backend>);
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        Backend::exit(&self.0, Wait::None, None);
+    }
+}
+
+impl Frontend {
+    /// Create a new front-end with an associated
+    /// back-end automatically.
+    pub fn new() -> Frontend {
+        FrontendBuilder::new().build()
+    }
+
+    /// Create a handle that starts tasks on this front-end's pool.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            backend: self.backend.clone(),
+            _owner: match self.shutdown {
+                Shutdown::WithFrontend => None,
+                Shutdown::WithLastHandle => Some(self.owner.clone())
+            }
+        }
+    }
+
+    /// The number of tasks that were added and did not complete yet.
+    /// This is only tracked when `FrontendBuilder::max_pending` is set.
+    pub fn pending_tasks(&self) -> Option<usize> {
+        self.backend.pool().pending_tasks()
+    }
+
+    /// The number of tasks that finished after their deadline.
+    pub fn deadline_misses(&self) -> usize {
+        self.backend.pool().deadline_misses()
+    }
+
+    /// Run the tasks that were started with `on_main_thread` and
+    /// are ready, on the calling thread. This is meant to be called
+    /// once per frame from the main loop. Returns the number of tasks
+    /// that were run.
+    pub fn run_main_thread_tasks(&mut self) -> usize {
+        Pool::run_main_thread_tasks(&self.backend)
+    }
+
+    /// Wait for `future` on the calling thread. Meanwhile the thread
+    /// runs ready tasks of the pool, including the ones waiting for the
+    /// main thread, instead of sleeping. In the thread back-end only the
+    /// tasks waiting for the main thread are run, the other tasks have
+    /// threads of their own.
+    pub fn wait_helping<T>(&mut self, future: &Future<T>) {
+        Backend::help(&self.backend, &future.signal())
+    }
+
+    /// List the workers that tasks can be pinned to with
+    /// `TaskBuilder::pin_to`.
+    pub fn workers(&self) -> Vec<WorkerId> {
+        self.backend.workers()
+    }
+
+    /// Change the number of workers of the pool. The tasks queued
+    /// on a retiring worker are handed over to the remaining ones. The
+    /// tasks still pinned to a removed worker run on another thread,
+    /// and adding a task pinned to it panics, the fiber back-end removes
+    /// the workers tasks were pinned to last. New workers reuse the ids
+    /// of the removed ones.
+    pub fn set_worker_count(&mut self, count: usize) {
+        Backend::set_worker_count(&self.backend, count)
+    }
+
+    /// Let the pool scale between `min` and `max` workers depending on
+    /// the number of ready tasks, `None` stops the scaling. Workers are
+    /// added when the ready tasks pile up, and retire after being idle
+    /// for a while, unless tasks were pinned to them. The thread
+    /// back-end starts a thread for each ready task, there is nothing
+    /// to scale, so this does nothing.
+    pub fn set_auto_scaling(&mut self, range: Option<(usize, usize)>) {
+        self.backend.set_auto_scaling(range)
+    }
+
+    /// The number of tasks the workers stole from each other. The
+    /// distance between workers that are not pinned with
+    /// `FrontendBuilder::affinity` is taken from the cores they
+    /// started on, the OS may move them later. The thread back-end
+    /// has no work stealing, so they are always zero.
+    pub fn steal_stats(&self) -> StealStats {
+        self.backend.steal_stats()
+    }
+
+    /// Create a new strand, tasks started on it with
+    /// `TaskBuilder::on_strand` run one at a time in submission order.
+    pub fn strand(&self) -> Strand {
+        Strand::new()
+    }
+
+    /// Create a new resource handle, tasks declare their accesses to
+    /// it with `TaskBuilder::reads` and `TaskBuilder::writes`.
+    pub fn resource(&self) -> Resource {
+        Resource::new()
+    }
+
+    /// Create a frame pipeline that keeps at most `frames_in_flight`
+    /// frames running at the same time.
+    pub fn pipeline(&self, frames_in_flight: usize) -> FramePipeline {
+        FramePipeline::new(frames_in_flight)
+    }
+
+    /// Run `f` on the pool every `period`, until the returned
+    /// handle is cancelled.
+    pub fn every<F>(&mut self, period: Duration, f: F) -> Periodic
+        where F: FnMut(&mut Schedule) + Send + 'static {
+        timer::every(self, period, f)
+    }
+
+    /// Set the time budget of a frame, `None` removes it. Once the
+    /// budget is spent, deferrable tasks that become ready are held
+    /// over to the next frame instead of being started.
+    pub fn set_frame_budget(&mut self, budget: Option<Duration>) {
+        self.backend.pool().set_frame_budget(budget)
+    }
+
+    /// Start a new frame, starting the tasks that were held over.
+    /// Returns the number of tasks that were postponed by the frame
+    /// that just ended.
+    pub fn next_frame(&mut self) -> usize {
+        Pool::next_frame(&self.backend)
+    }
+
+    /// The total number of tasks that were held over to a later frame.
+    pub fn postponed_tasks(&self) -> usize {
+        self.backend.pool().postponed_tasks()
+    }
+
+    /// Stop the queue, using selected wait mode. Returns false if
+    /// some tasks were left running. With `Wait::Pending` the calling
+    /// thread runs the tasks waiting for the main thread meanwhile,
+    /// and the tasks held over by the frame budget are started.
+    pub fn die(self, wait: Wait) -> bool {
+        Backend::exit(&self.backend, wait, None).running == 0
+    }
+
+    /// Stop the queue like `die`, but stop waiting once `timeout`
+    /// expired. The report tells what happened to the tasks.
+    pub fn die_timeout(self, wait: Wait, timeout: Duration) -> ShutdownReport {
+        Backend::exit(&self.backend, wait, Some(timeout))
+    }
+}
+
+impl FrontendBuilder {
+    /// Create the front-end and its back-end.
+    pub fn build(self) -> Frontend {
+        let backend = Backend::new(&self);
+        let back = backend.clone();
+        let front = Frontend {
+            backend: back,
+            owner: Arc::new(Owner(backend)),
+            shutdown: self.shutdown
+        };
+        front
+    }
+}
+
+impl Schedule for Frontend {
+    fn add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>) {
+        self.backend.add_task(task, after)
+    }
+
+    fn add_task_with(&mut self,
+                     task: Box<FnBox+Send>,
+                     after: Vec<Signal>,
+                     options: TaskOptions) {
+        self.backend.add_task_with(task, after, options)
+    }
+
+    fn try_add_task_with(&mut self,
+                         task: Box<FnBox+Send>,
+                         after: Vec<Signal>,
+                         options: TaskOptions) -> Result<(), Full> {
+        self.backend.try_add_task_with(task, after, options)
+    }
+
+    fn add_task_box(&mut self,
+                    task: TaskBox,
+                    after: Vec<Signal>,
+                    options: TaskOptions) {
+        self.backend.add_task_box(task, after, options)
+    }
+
+    fn try_add_task_box(&mut self,
+                        task: TaskBox,
+                        after: Vec<Signal>,
+                        options: TaskOptions) -> Result<(), Full> {
+        self.backend.try_add_task_box(task, after, options)
+    }
+}
+
+impl Schedule for Spawner {
+    fn add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>) {
+        self.backend.add_task(task, after)
+    }
+
+    fn add_task_with(&mut self,
+                     task: Box<FnBox+Send>,
+                     after: Vec<Signal>,
+                     options: TaskOptions) {
+        self.backend.add_task_with(task, after, options)
+    }
+
+    fn try_add_task_with(&mut self,
+                         task: Box<FnBox+Send>,
+                         after: Vec<Signal>,
+                         options: TaskOptions) -> Result<(), Full> {
+        self.backend.try_add_task_with(task, after, options)
+    }
+
+    fn add_task_box(&mut self,
+                    task: TaskBox,
+                    after: Vec<Signal>,
+                    options: TaskOptions) {
+        self.backend.add_task_box(task, after, options)
+    }
+
+    fn try_add_task_box(&mut self,
+                        task: TaskBox,
+                        after: Vec<Signal>,
+                        options: TaskOptions) -> Result<(), Full> {
+        self.backend.try_add_task_box(task, after, options)
+    }
+}
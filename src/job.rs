@@ -0,0 +1,49 @@
+//! A task along with the bookkeeping the back-end does for it. The
+//! bookkeeping is kept next to the task rather than in closures
+//! wrapping it, so it does not cost an allocation per task.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use {Schedule, TaskBox};
+use counters::Counters;
+use deadline::Deadlines;
+use limit::Slot;
+
+pub struct Job {
+    task: TaskBox,
+    counters: Arc<Counters>,
+    deadline: Option<(Arc<Deadlines>, Instant)>,
+    /// Released once the job is dropped, whether it ran or not
+    slot: Option<Slot>
+}
+
+impl Job {
+    /// Count a new task.
+    pub fn new(task: TaskBox, slot: Option<Slot>, counters: &Arc<Counters>) -> Job {
+        counters.add();
+        Job {
+            task: task,
+            counters: counters.clone(),
+            deadline: None,
+            slot: slot
+        }
+    }
+
+    /// Report the task if it finishes after `deadline`.
+    pub fn deadline(mut self, deadlines: &Arc<Deadlines>, deadline: Instant) -> Job {
+        self.deadline = Some((deadlines.clone(), deadline));
+        self
+    }
+
+    /// Run the task.
+    pub fn run(self, sched: &mut Schedule) {
+        let Job{task, counters, deadline, slot} = self;
+        let _slot = slot;
+        let _finished = Counters::start(&counters);
+        task.call(sched);
+        if let Some((deadlines, deadline)) = deadline {
+            deadlines.finished(deadline);
+        }
+    }
+}
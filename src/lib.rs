@@ -1,4 +1,5 @@
 #![deny(missing_docs)]
+#![cfg_attr(feature="unstable", feature(test))]
 
 //! A simple task queue with dependency tracking.
 
@@ -19,26 +20,58 @@ extern crate bran;
 
 extern crate future_pulse;
 
+#[cfg(all(test, feature="unstable"))]
+extern crate test;
+
 #[cfg(feature="fiber")]
 mod fiber;
 
 #[cfg(feature="thread")]
 mod thread;
 
+mod front;
+mod pool;
 mod task;
+mod task_box;
+mod try_task;
+mod shared;
+mod cache;
 mod fnbox;
-
+mod main_thread;
+mod strand;
+mod resource;
+mod frame;
+mod timer;
+mod deadline;
+mod budget;
+mod limit;
+mod counters;
+mod job;
+mod affinity;
+mod waiting;
 #[cfg(feature="fiber")]
-pub use fiber::front::Frontend;
+mod topology;
 
-#[cfg(feature="thread")]
-pub use thread::Frontend;
+pub use front::{Frontend, Spawner};
 
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
 use pulse::Signal;
+use deadline::DeadlineMissFn;
 
 pub use fnbox::FnBox;
-pub use self::task::{task, TaskBuilder};
+pub use self::task::{task, TaskBuilder, TaskOptions};
+pub use self::task_box::TaskBox;
+pub use self::try_task::{try_task, TryTaskBuilder, TryFuture, Dependency};
+pub use self::strand::Strand;
+pub use self::resource::Resource;
+pub use self::frame::{FramePipeline, Frame};
+pub use self::timer::Periodic;
+pub use self::waiting::{wait, block_on};
+pub use self::shared::SharedFuture;
+pub use self::cache::{TaskCache, Eviction};
 
 /// Wait mode for the front-end termination.
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -51,6 +84,181 @@ pub enum Wait {
     Pending,
 }
 
+/// What happened to the tasks of a `Frontend` that was stopped.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct ShutdownReport {
+    /// Tasks that ran to completion.
+    pub completed: usize,
+    /// Tasks that never started, they won't run anymore.
+    pub dropped: usize,
+    /// Tasks that were still running when the front-end stopped
+    /// waiting for them.
+    pub running: usize,
+    /// The timeout expired before the wait was over.
+    pub timed_out: bool,
+}
+
+/// Counts of the tasks the workers stole from each other. A steal is
+/// local when the workers share a cache or a NUMA node.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct StealStats {
+    /// Steals from a close worker.
+    pub local: usize,
+    /// Steals from a remote worker.
+    pub remote: usize,
+}
+
+/// Identifies a single worker of a `Frontend`, see `Frontend::workers`.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct WorkerId(usize);
+
+/// Selects the thread a task is allowed to run on.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Placement {
+    /// Any worker of the pool.
+    Any,
+    /// Only the thread calling `Frontend::run_main_thread_tasks`.
+    MainThread,
+    /// Only the selected worker.
+    Worker(WorkerId),
+}
+
+/// How the workers choose among the ready tasks.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Scheduling {
+    /// Workers run whatever ready task they find first.
+    WorkStealing,
+    /// Ready tasks with a deadline run before the others, the
+    /// earliest deadline first.
+    EarliestDeadline,
+}
+
+/// What `Schedule::add_task` does when the pool already holds its
+/// maximum number of pending tasks.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Backpressure {
+    /// Block the submitting thread until a task completes.
+    Block,
+    /// Suspend the submitting fiber until a task completes, a thread
+    /// that is not running a fiber is blocked.
+    Suspend,
+}
+
+/// Error returned by `Schedule::try_add_task` when the pool already
+/// holds its maximum number of pending tasks. The task is given back.
+pub struct Full(pub Box<FnBox+Send>);
+
+impl fmt::Debug for Full {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Full(..)")
+    }
+}
+
+/// Selects which handles keep a pool running.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Shutdown {
+    /// The pool stops when the `Frontend` is dropped, the `Spawner`s
+    /// left behind can't start tasks anymore.
+    WithFrontend,
+    /// The pool stops once the `Frontend` and all the `Spawner`s
+    /// are dropped.
+    WithLastHandle,
+}
+
+/// Configures and creates a `Frontend`.
+pub struct FrontendBuilder {
+    name: String,
+    shutdown: Shutdown,
+    scheduling: Scheduling,
+    deadline_miss: Option<DeadlineMissFn>,
+    max_pending: Option<(usize, Backpressure)>,
+    workers: usize,
+    affinity: Option<Vec<usize>>,
+    stack_size: Option<usize>
+}
+
+impl FrontendBuilder {
+    /// Create a builder with the default configuration.
+    pub fn new() -> FrontendBuilder {
+        FrontendBuilder {
+            name: "Worker".to_string(),
+            shutdown: Shutdown::WithFrontend,
+            scheduling: Scheduling::WorkStealing,
+            deadline_miss: None,
+            max_pending: None,
+            workers: num_cpus::get(),
+            affinity: None,
+            stack_size: None
+        }
+    }
+
+    /// Name the pool, the worker threads are named after it. This
+    /// tells the pools apart when using several of them.
+    pub fn name(mut self, name: &str) -> FrontendBuilder {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Set the number of workers the pool starts with, it defaults to
+    /// the number of CPUs.
+    pub fn workers(mut self, count: usize) -> FrontendBuilder {
+        assert!(count > 0, "a pool needs at least one worker");
+        self.workers = count;
+        self
+    }
+
+    /// Pin the workers to CPU cores, the workers are numbered from one
+    /// and worker `n` runs on `cores[(n - 1) % cores.len()]`. Only
+    /// supported on Linux.
+    pub fn affinity(mut self, cores: Vec<usize>) -> FrontendBuilder {
+        assert!(cores.len() > 0, "the core list must not be empty");
+        assert!(cores.iter().all(|&core| core < affinity::MAX_CORES),
+                "the cores must be below {}", affinity::MAX_CORES);
+        self.affinity = Some(cores);
+        self
+    }
+
+    /// Set the stack size of the threads of the pool. In the fiber
+    /// back-end this is the stack of the workers, which the tasks
+    /// started with `TaskBuilder::no_suspend` run on. The fibers'
+    /// stacks are not changed, see `TaskBuilder::stack_size`.
+    pub fn stack_size(mut self, size: usize) -> FrontendBuilder {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Select which handles keep the pool running.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> FrontendBuilder {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Select how the workers choose among the ready tasks. The thread
+    /// back-end starts every ready task at once, so this only changes
+    /// the fiber back-end.
+    pub fn scheduling(mut self, scheduling: Scheduling) -> FrontendBuilder {
+        self.scheduling = scheduling;
+        self
+    }
+
+    /// Call `f` each time a task finishes after its deadline, with how
+    /// late it finished. The callback runs on the worker that ran the task.
+    pub fn on_deadline_miss<F>(mut self, f: F) -> FrontendBuilder
+        where F: Fn(Duration) + Send + Sync + 'static {
+        self.deadline_miss = Some(Arc::new(f));
+        self
+    }
+
+    /// Limit the number of pending tasks to `max`. A task is pending
+    /// from the moment it is added until it completes. `mode` selects
+    /// what happens to a submitter when the limit is reached, while
+    /// `Schedule::try_add_task` fails instead.
+    pub fn max_pending(mut self, max: usize, mode: Backpressure) -> FrontendBuilder {
+        self.max_pending = Some((max, mode));
+        self
+    }
+}
+
 /// Abstract representation of a the scheduler, allow for new tasks
 /// to be created and enqueued.
 pub trait Schedule {
@@ -58,4 +266,49 @@ pub trait Schedule {
     /// tasks in-flight. The task will actually start as soon as all 
     /// dependencies are finished.
     fn add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>);
+
+    /// Add a new task like `add_task`, using the supplied options
+    /// to decide where and how it is run. A scheduler that doesn't
+    /// override this ignores the options.
+    fn add_task_with(&mut self,
+                     task: Box<FnBox+Send>,
+                     after: Vec<Signal>,
+                     _options: TaskOptions) {
+        self.add_task(task, after)
+    }
+
+    /// Add a new task like `add_task`, but fail instead of waiting if
+    /// the scheduler can't accept more tasks.
+    fn try_add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>)
+        -> Result<(), Full> {
+        self.try_add_task_with(task, after, TaskOptions::default())
+    }
+
+    /// Add a new task like `add_task_with`, but fail instead of waiting
+    /// if the scheduler can't accept more tasks.
+    fn try_add_task_with(&mut self,
+                         task: Box<FnBox+Send>,
+                         after: Vec<Signal>,
+                         options: TaskOptions) -> Result<(), Full> {
+        self.add_task_with(task, after, options);
+        Ok(())
+    }
+
+    /// Add a new task like `add_task_with`, the scheduler may keep a
+    /// small closure without boxing it.
+    fn add_task_box(&mut self,
+                    task: TaskBox,
+                    after: Vec<Signal>,
+                    options: TaskOptions) {
+        self.add_task_with(task.into_boxed(), after, options)
+    }
+
+    /// Add a new task like `add_task_box`, but fail instead of waiting
+    /// if the scheduler can't accept more tasks.
+    fn try_add_task_box(&mut self,
+                        task: TaskBox,
+                        after: Vec<Signal>,
+                        options: TaskOptions) -> Result<(), Full> {
+        self.try_add_task_with(task.into_boxed(), after, options)
+    }
 }
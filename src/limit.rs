@@ -0,0 +1,103 @@
+//! Limit on the number of pending tasks of a pool. A task is pending
+//! from the moment it is added until it completes or is dropped.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Condvar};
+
+use pulse::{Signal, Pulse, Signals};
+
+use Backpressure;
+
+struct State {
+    pending: usize,
+    /// The suspended submitters, the first to wait is woken first
+    waiters: VecDeque<Pulse>
+}
+
+pub struct Limit {
+    max: usize,
+    mode: Backpressure,
+    state: Mutex<State>,
+    freed: Condvar
+}
+
+/// Releases the slot of a task when the task is dropped, which
+/// happens after it ran, or when it is discarded without running.
+pub struct Slot(Arc<Limit>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+impl Limit {
+    pub fn new(max: usize, mode: Backpressure) -> Arc<Limit> {
+        assert!(max > 0, "the pending task limit must not be zero");
+        Arc::new(Limit {
+            max: max,
+            mode: mode,
+            state: Mutex::new(State {
+                pending: 0,
+                waiters: VecDeque::new()
+            }),
+            freed: Condvar::new()
+        })
+    }
+
+    /// Take a slot, waiting for one to be free if needed.
+    pub fn acquire(this: &Arc<Limit>) -> Slot {
+        let mut state = this.state.lock().unwrap();
+        while state.pending >= this.max {
+            match this.mode {
+                Backpressure::Block => {
+                    state = this.freed.wait(state).unwrap();
+                }
+                Backpressure::Suspend => {
+                    // waiting on a signal suspends the fiber if
+                    // there is one, instead of blocking the thread
+                    let (signal, pulse) = Signal::new();
+                    state.waiters.push_back(pulse);
+                    drop(state);
+                    let _ = signal.wait();
+                    state = this.state.lock().unwrap();
+                }
+            }
+        }
+        state.pending += 1;
+        Slot(this.clone())
+    }
+
+    /// Take a slot without waiting, for a task taking over the slot
+    /// of a task that is about to complete. The limit is exceeded
+    /// until that task releases its slot.
+    pub fn take_over(this: &Arc<Limit>) -> Slot {
+        this.state.lock().unwrap().pending += 1;
+        Slot(this.clone())
+    }
+
+    /// Take a slot if one is free.
+    pub fn try_acquire(this: &Arc<Limit>) -> Option<Slot> {
+        let mut state = this.state.lock().unwrap();
+        if state.pending >= this.max {
+            return None;
+        }
+        state.pending += 1;
+        Some(Slot(this.clone()))
+    }
+
+    fn release(&self) {
+        let waiter = {
+            let mut state = self.state.lock().unwrap();
+            state.pending -= 1;
+            self.freed.notify_one();
+            state.waiters.pop_front()
+        };
+        waiter.map(|p| p.pulse());
+    }
+
+    /// The number of pending tasks.
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().pending
+    }
+}
@@ -0,0 +1,50 @@
+//! Queue of the tasks that may only run on the main thread. The
+//! main thread has to pump it with `Frontend::run_main_thread_tasks`.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use Schedule;
+use job::Job;
+
+pub struct MainQueue(Mutex<VecDeque<Job>>);
+
+impl MainQueue {
+    pub fn new() -> MainQueue {
+        MainQueue(Mutex::new(VecDeque::new()))
+    }
+
+    /// Queue a task that is ready to run.
+    pub fn push(&self, task: Job) {
+        self.0.lock().unwrap().push_back(task);
+    }
+
+    /// Run the first queued task, returns false if there is none.
+    pub fn run_one(&self, sched: &mut Schedule) -> bool {
+        let task = self.0.lock().unwrap().pop_front();
+        match task {
+            Some(task) => {
+                task.run(sched);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Run tasks until the queue is empty, this includes tasks that
+    /// became ready while pumping. Returns the number of tasks run.
+    pub fn run(&self, sched: &mut Schedule) -> usize {
+        let mut count = 0;
+        loop {
+            // the lock is released before running, the task may add more
+            let task = self.0.lock().unwrap().pop_front();
+            match task {
+                Some(task) => {
+                    task.run(sched);
+                    count += 1;
+                }
+                None => return count
+            }
+        }
+    }
+}
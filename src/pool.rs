@@ -0,0 +1,229 @@
+//! The part of the back-ends that does not depend on how the tasks
+//! are run: limiting, counting and holding over the tasks, and the
+//! queue of the main thread.
+
+use std::cmp;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use pulse::*;
+
+use {Schedule, FnBox, TaskBox, TaskOptions, FrontendBuilder, Full};
+use {Placement, WorkerId};
+use main_thread::MainQueue;
+use deadline::Deadlines;
+use limit::{Limit, Slot};
+use counters::Counters;
+use job::Job;
+use budget::Budget;
+use waiting;
+
+/// Implemented by the back-ends, to run the tasks the pool made ready.
+pub trait Run: Send + Sync + Sized + 'static {
+    /// The shared state of the back-end.
+    fn pool(&self) -> &Pool;
+
+    /// Called when a task pinned to `worker` is added, panics if the
+    /// pool has no such worker.
+    fn pin(&self, worker: WorkerId);
+
+    /// Run a task whose dependencies are all completed, and that
+    /// was not held over by the frame budget. `woken` is set if the
+    /// task waited for its dependencies, rather than being ready when
+    /// it was added.
+    fn run(back: Arc<Self>, task: Job, options: TaskOptions, woken: bool);
+}
+
+/// State shared by the back-ends.
+pub struct Pool {
+    pub main: MainQueue,
+    pub counters: Arc<Counters>,
+    deadlines: Arc<Deadlines>,
+    budget: Budget,
+    limit: Option<Arc<Limit>>
+}
+
+impl Pool {
+    /// Create the state of a new back-end.
+    pub fn new(config: &FrontendBuilder) -> Pool {
+        Pool {
+            main: MainQueue::new(),
+            counters: Counters::new(),
+            deadlines: Deadlines::new(config.deadline_miss.clone()),
+            budget: Budget::new(),
+            limit: config.max_pending.map(|(max, mode)| Limit::new(max, mode))
+        }
+    }
+
+    /// Start a task that will run once all the Handle's have
+    /// been completed, waiting for a slot if the back-end has
+    /// too many pending tasks.
+    pub fn start<B: Run>(back: Arc<B>,
+                         task: TaskBox,
+                         after: Vec<Signal>,
+                         options: TaskOptions) {
+        let slot = back.pool().limit.as_ref().map(|limit| {
+            if options.retry {
+                Limit::take_over(limit)
+            } else {
+                Limit::acquire(limit)
+            }
+        });
+        Pool::add(back, task, slot, after, options)
+    }
+
+    /// Start a task like `start`, fails if the back-end has too
+    /// many pending tasks.
+    pub fn try_start<B: Run>(back: Arc<B>,
+                             task: TaskBox,
+                             after: Vec<Signal>,
+                             options: TaskOptions) -> Result<(), Full> {
+        let slot = match back.pool().limit {
+            Some(ref limit) if options.retry => Some(Limit::take_over(limit)),
+            Some(ref limit) => match Limit::try_acquire(limit) {
+                Some(slot) => Some(slot),
+                None => return Err(Full(task.into_boxed()))
+            },
+            None => None
+        };
+        Pool::add(back, task, slot, after, options);
+        Ok(())
+    }
+
+    fn add<B: Run>(back: Arc<B>,
+                   task: TaskBox,
+                   slot: Option<Slot>,
+                   mut after: Vec<Signal>,
+                   options: TaskOptions) {
+        if let Placement::Worker(worker) = options.placement {
+            back.pin(worker);
+        }
+
+        // Create the wait signal if needed
+        let signal = if after.len() == 0 {
+            Signal::pulsed()
+        } else if after.len() == 1 {
+            after.pop().unwrap()
+        } else {
+            Barrier::new(&after).signal()
+        };
+
+        let task = {
+            let pool = back.pool();
+            let task = Job::new(task, slot, &pool.counters);
+            match options.deadline {
+                Some(deadline) => task.deadline(&pool.deadlines, deadline),
+                None => task
+            }
+        };
+
+        // a task that is ready when added was usually spawned by a task
+        // that keeps running, one that waited was woken by the task
+        // that just completed
+        let woken = signal.is_pending();
+        signal.callback(move || Pool::ready(back, task, options, woken));
+    }
+
+    /// Hand a task whose dependencies are all completed to the
+    /// back-end, unless the frame budget is spent.
+    fn ready<B: Run>(back: Arc<B>, task: Job, options: TaskOptions, woken: bool) {
+        let (task, options) = match back.pool().budget.hold(task, options) {
+            Some(task) => task,
+            None => return
+        };
+        back.pool().counters.ready();
+        B::run(back, task, options, woken)
+    }
+
+    /// Run the tasks that are waiting for the main thread.
+    pub fn run_main_thread_tasks<B: Run>(back: &Arc<B>) -> usize {
+        back.pool().main.run(&mut back.clone())
+    }
+
+    /// Set the time budget of a frame.
+    pub fn set_frame_budget(&self, budget: Option<Duration>) {
+        self.budget.set(budget)
+    }
+
+    /// Start a new frame, returns how many tasks were held over.
+    pub fn next_frame<B: Run>(back: &Arc<B>) -> usize {
+        let held = back.pool().budget.next_frame();
+        let count = held.len();
+        for (task, options) in held {
+            Pool::ready(back.clone(), task, options, false);
+        }
+        count
+    }
+
+    /// Wait until every task that was added finished, returns false
+    /// if the deadline passed first. Meanwhile the calling thread runs
+    /// the tasks waiting for the main thread, and the tasks held over
+    /// by the frame budget are started, no frame would start them.
+    pub fn flush<B: Run>(back: &Arc<B>, deadline: Option<Instant>) -> bool {
+        back.pool().budget.set(None);
+        Pool::next_frame(back);
+        loop {
+            Pool::run_main_thread_tasks(back);
+            // the main thread tasks don't wake up the counters, so
+            // they are checked again after a while
+            let poll = Instant::now() + waiting::IDLE;
+            let until = deadline.map_or(poll, |deadline| cmp::min(deadline, poll));
+            if back.pool().counters.wait_empty(Some(until)) {
+                return true;
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return false;
+            }
+        }
+    }
+
+    /// The total number of tasks that were held over.
+    pub fn postponed_tasks(&self) -> usize {
+        self.budget.postponed()
+    }
+
+    /// The number of tasks that were added and did not complete yet,
+    /// only tracked when the number of pending tasks is limited.
+    pub fn pending_tasks(&self) -> Option<usize> {
+        self.limit.as_ref().map(|limit| limit.pending())
+    }
+
+    /// The number of tasks that finished after their deadline.
+    pub fn deadline_misses(&self) -> usize {
+        self.deadlines.misses()
+    }
+}
+
+impl<B: Run> Schedule for Arc<B> {
+    fn add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>) {
+        self.add_task_with(task, after, TaskOptions::default())
+    }
+
+    fn add_task_with(&mut self,
+                     task: Box<FnBox+Send>,
+                     after: Vec<Signal>,
+                     options: TaskOptions) {
+        Pool::start(self.clone(), TaskBox::boxed(task), after, options)
+    }
+
+    fn try_add_task_with(&mut self,
+                         task: Box<FnBox+Send>,
+                         after: Vec<Signal>,
+                         options: TaskOptions) -> Result<(), Full> {
+        Pool::try_start(self.clone(), TaskBox::boxed(task), after, options)
+    }
+
+    fn add_task_box(&mut self,
+                    task: TaskBox,
+                    after: Vec<Signal>,
+                    options: TaskOptions) {
+        Pool::start(self.clone(), task, after, options)
+    }
+
+    fn try_add_task_box(&mut self,
+                        task: TaskBox,
+                        after: Vec<Signal>,
+                        options: TaskOptions) -> Result<(), Full> {
+        Pool::try_start(self.clone(), task, after, options)
+    }
+}
@@ -0,0 +1,84 @@
+//! Resource handles, used to derive the ordering of the tasks from
+//! the data they declare to read and write. Readers of a resource run
+//! in parallel, writers run alone, all in submission order.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use pulse::Signal;
+
+/// How a task accesses a resource
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub enum Access {
+    /// Shared access
+    Read,
+    /// Exclusive access
+    Write
+}
+
+struct State {
+    /// The last task that wrote to the resource
+    writer: Option<Signal>,
+    /// The tasks that read the resource since the last write
+    readers: Vec<Signal>
+}
+
+/// A handle for a piece of data shared by several tasks. The handle
+/// does not own the data, it only tracks which tasks access it.
+/// Clones of the handle refer to the same resource.
+#[derive(Clone)]
+pub struct Resource {
+    state: Arc<Mutex<State>>
+}
+
+impl Resource {
+    /// Create a new resource that was never accessed.
+    pub fn new() -> Resource {
+        Resource {
+            state: Arc::new(Mutex::new(State {
+                writer: None,
+                readers: Vec::new()
+            }))
+        }
+    }
+
+    fn id(&self) -> usize {
+        &*self.state as *const Mutex<State> as usize
+    }
+}
+
+/// Register a task accessing the listed resources, `done` must be
+/// asserted when it completes. Returns the signals the task has to
+/// wait on before it can start.
+///
+/// All the resources are locked together, in a fixed order, so that
+/// two tasks registering at the same time can't end up waiting on
+/// each other.
+pub fn acquire(access: &[(Resource, Access)], done: &Signal) -> Vec<Signal> {
+    // a resource both read and written by the task is written
+    let mut access: Vec<&(Resource, Access)> = access.iter().collect();
+    access.sort_by(|a, b| a.0.id().cmp(&b.0.id()).then(b.1.cmp(&a.1)));
+    access.dedup_by_key(|a| a.0.id());
+
+    let mut guards: Vec<(MutexGuard<State>, Access)> = access.iter()
+        .map(|&&(ref res, mode)| (res.state.lock().unwrap(), mode))
+        .collect();
+
+    let mut wait = Vec::new();
+    for &mut (ref mut state, mode) in guards.iter_mut() {
+        match mode {
+            Access::Read => {
+                if let Some(ref writer) = state.writer {
+                    wait.push(writer.clone());
+                }
+                state.readers.retain(|s| s.is_pending());
+                state.readers.push(done.clone());
+            }
+            Access::Write => {
+                wait.extend(state.writer.take());
+                wait.extend(state.readers.drain(..));
+                state.writer = Some(done.clone());
+            }
+        }
+    }
+    wait
+}
@@ -0,0 +1,81 @@
+//! Results that can be read by any number of readers.
+
+use std::sync::{Arc, Mutex};
+
+use pulse::{Signal, Signals};
+use future_pulse::Future;
+
+use waiting;
+
+/// A handle to the result of a task that can be cloned, each clone
+/// can read the result. It is cheap to clone, clones share the result.
+/// See `TaskBuilder::start_shared`.
+pub struct SharedFuture<T> {
+    signal: Signal,
+    value: Arc<Mutex<Option<Arc<T>>>>
+}
+
+impl<T> Clone for SharedFuture<T> {
+    fn clone(&self) -> SharedFuture<T> {
+        SharedFuture {
+            signal: self.signal.clone(),
+            value: self.value.clone()
+        }
+    }
+}
+
+impl<T: Send + 'static> SharedFuture<T> {
+    /// Share the result of `future`.
+    pub fn new(future: Future<T>) -> SharedFuture<T> {
+        let (signal, pulse) = Signal::new();
+        let value = Arc::new(Mutex::new(None));
+        let slot = value.clone();
+        future.signal().callback(move || {
+            // the signal also fires when the task panicked and its
+            // setter was dropped, there is no value to take then
+            if future.signal().wait().is_ok() {
+                *slot.lock().unwrap() = Some(Arc::new(future.get()));
+            }
+            // the readers wake up once the value is in place
+            pulse.pulse();
+        });
+
+        SharedFuture {
+            signal: signal,
+            value: value
+        }
+    }
+}
+
+impl<T: Send + 'static> From<Future<T>> for SharedFuture<T> {
+    fn from(future: Future<T>) -> SharedFuture<T> {
+        SharedFuture::new(future)
+    }
+}
+
+impl<T> SharedFuture<T> {
+    /// The signal asserted once the result is available.
+    pub fn signal(&self) -> Signal {
+        self.signal.clone()
+    }
+
+    /// Check if the result is available.
+    pub fn is_ready(&self) -> bool {
+        !self.signal.is_pending()
+    }
+
+    /// Wait for the result and return a reference to it, for results
+    /// that can't or shouldn't be copied. See `fibe::wait`. Panics
+    /// if the task panicked.
+    pub fn get_arc(&self) -> Arc<T> {
+        let _ = waiting::wait(self.signal());
+        self.value.lock().unwrap().clone().expect("the task did not complete")
+    }
+}
+
+impl<T: Clone> SharedFuture<T> {
+    /// Wait for the result and return a copy of it, see `fibe::wait`.
+    pub fn get(&self) -> T {
+        (*self.get_arc()).clone()
+    }
+}
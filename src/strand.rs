@@ -0,0 +1,23 @@
+//! Strands serialize groups of tasks. Tasks started on the same strand
+//! never run concurrently, and run in the order they were started.
+
+use resource::Resource;
+
+/// A serial queue of tasks. Each task started on the strand is made to
+/// depend on the previous one, so no worker is ever blocked waiting
+/// for the strand. A strand is cheap to clone, clones share the queue.
+#[derive(Clone)]
+pub struct Strand(Resource);
+
+impl Strand {
+    /// Create a new empty strand.
+    pub fn new() -> Strand {
+        Strand(Resource::new())
+    }
+
+    /// The resource behind the strand, every task on the strand
+    /// writes to it.
+    pub fn resource(&self) -> &Resource {
+        &self.0
+    }
+}
@@ -1,16 +1,67 @@
 
-use pulse::Signal;
+use std::time::{Duration, Instant};
+
+use pulse::{Signal, Barrier};
 use future_pulse::Future;
-use {Schedule, FnBox};
+use {Schedule, TaskBox, Placement, WorkerId, Strand, Full, SharedFuture};
+use resource::{self, Resource, Access};
+use timer;
+
+/// Options controlling how a scheduler runs a task
+#[derive(Clone, Debug)]
+pub struct TaskOptions {
+    /// The thread the task is allowed to run on
+    pub placement: Placement,
+
+    /// The time by which the task should be finished
+    pub deadline: Option<Instant>,
+
+    /// The task may be held over to the next frame when the frame's
+    /// budget is spent
+    pub deferrable: bool,
+
+    /// The task never waits, so it can run without a fiber of its own
+    pub no_suspend: bool,
+
+    /// The stack the task needs, if more than the pool's default
+    pub stack_size: Option<usize>,
+
+    /// The task retries the task adding it, which is about to
+    /// complete. It takes over its pending slot, see
+    /// `FrontendBuilder::max_pending`, instead of waiting for one
+    pub retry: bool
+}
+
+impl Default for TaskOptions {
+    fn default() -> TaskOptions {
+        TaskOptions {
+            placement: Placement::Any,
+            deadline: None,
+            deferrable: false,
+            no_suspend: false,
+            stack_size: None,
+            retry: false
+        }
+    }
+}
 
 /// A structure to help build a task
 pub struct TaskBuilder<T> {
     /// The task to be run
-    task: Box<FnBox+Send>,
+    task: TaskBox,
 
     /// The signals to wait on
     wait: Vec<Signal>,
 
+    /// How the task should be run
+    options: TaskOptions,
+
+    /// The resources the task accesses
+    access: Vec<(Resource, Access)>,
+
+    /// How long to wait before the task can start
+    delay: Option<Duration>,
+
     /// The results
     result: Future<T>
 }
@@ -22,12 +73,154 @@ impl<T> TaskBuilder<T> {
         self
     }
 
+    /// Start the task only after `delay` has elapsed, counting from
+    /// the call to `start`
+    pub fn delay(mut self, delay: Duration) -> TaskBuilder<T> {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// The task should be finished by `deadline`. With the
+    /// `Scheduling::EarliestDeadline` mode the task is prioritized
+    /// accordingly, a miss is always reported to the `Frontend`
+    pub fn deadline(mut self, deadline: Instant) -> TaskBuilder<T> {
+        self.options.deadline = Some(deadline);
+        self
+    }
+
+    /// Allow the task to be held over to the next frame if the budget
+    /// set with `Frontend::set_frame_budget` is spent when it is ready
+    pub fn deferrable(mut self) -> TaskBuilder<T> {
+        self.options.deferrable = true;
+        self
+    }
+
+    /// Promise that the task never suspends. The fiber back-end then
+    /// runs it on the stack of the worker instead of in a fiber, which
+    /// saves the context switches. Such a task can't be moved to a
+    /// fiber once it started, if it has to wait it must use `fibe::wait`
+    /// or `fibe::block_on`, which run the worker's other tasks until the
+    /// wait is over. `Future::get` or `Signal::wait` block the worker,
+    /// which deadlocks a pool with one worker if the task waited for
+    /// is queued on it
+    pub fn no_suspend(mut self) -> TaskBuilder<T> {
+        self.options.no_suspend = true;
+        self
+    }
+
+    /// Run the task on a stack of at least `size` bytes, for deep
+    /// recursion. The task gets a thread of its own, whose stack is
+    /// guarded against overflowing. A task pinned with `pin_to` or
+    /// `on_main_thread` runs on the stack of that thread instead, so
+    /// the size is ignored. In the fiber back-end the thread does not
+    /// wait for a worker, even with `Scheduling::EarliestDeadline`, but
+    /// there are at most as many such threads as the pool started
+    /// with workers, the other tasks wait for one of them
+    pub fn stack_size(mut self, size: usize) -> TaskBuilder<T> {
+        self.options.stack_size = Some(size);
+        self
+    }
+
+    /// Run the task on the main thread, the next time it calls
+    /// `Frontend::run_main_thread_tasks`
+    pub fn on_main_thread(mut self) -> TaskBuilder<T> {
+        self.options.placement = Placement::MainThread;
+        self
+    }
+
+    /// Run the task on the selected worker only. Starting the task
+    /// panics if the worker was removed, see `Frontend::set_worker_count`
+    pub fn pin_to(mut self, worker: WorkerId) -> TaskBuilder<T> {
+        self.options.placement = Placement::Worker(worker);
+        self
+    }
+
+    /// Run the task on `strand`, after all the tasks that were
+    /// previously started on it
+    pub fn on_strand(self, strand: &Strand) -> TaskBuilder<T> {
+        self.writes(strand.resource())
+    }
+
+    /// Declare that the task reads `res`. The task runs after the
+    /// previous writer, and in parallel with other readers
+    pub fn reads(mut self, res: &Resource) -> TaskBuilder<T> {
+        self.access.push((res.clone(), Access::Read));
+        self
+    }
+
+    /// Declare that the task writes `res`. The task runs alone, after
+    /// all the previously started readers and writers
+    pub fn writes(mut self, res: &Resource) -> TaskBuilder<T> {
+        self.access.push((res.clone(), Access::Write));
+        self
+    }
+
+    /// How the task will be run
+    pub fn options(&self) -> &TaskOptions {
+        &self.options
+    }
+
     /// Start the task using the supplied scheduler
     pub fn start(self, sched: &mut Schedule) -> Future<T> {
-        let TaskBuilder{task, wait, result} = self;
-        sched.add_task(task, wait);
+        let TaskBuilder{task, mut wait, options, access, delay, result} = self;
+        if let Some(delay) = delay {
+            wait.push(timer::after(delay));
+        }
+        if access.len() > 0 {
+            wait.extend(resource::acquire(&access, &result.signal()));
+        }
+        sched.add_task_box(task, wait, options);
         result
     }
+
+    /// Start the task like `start`, the result can be read by any
+    /// number of other tasks
+    pub fn start_shared(self, sched: &mut Schedule) -> SharedFuture<T>
+        where T: Send + 'static {
+        SharedFuture::new(self.start(sched))
+    }
+
+    /// Start the task like `start`, but give the builder back instead
+    /// of waiting if the scheduler can't accept more tasks
+    pub fn try_start(self, sched: &mut Schedule) -> Result<Future<T>, TaskBuilder<T>> {
+        let TaskBuilder{task, wait, options, access, delay, result} = self;
+        let mut after = wait.clone();
+        if let Some(delay) = delay {
+            after.push(timer::after(delay));
+        }
+
+        // the resources are only registered once the task is accepted,
+        // until then a gate keeps it from starting
+        let gate = if access.len() > 0 {
+            let (signal, pulse) = Signal::new();
+            after.push(signal);
+            Some(pulse)
+        } else {
+            None
+        };
+
+        match sched.try_add_task_box(task, after, options.clone()) {
+            Ok(()) => {
+                if let Some(gate) = gate {
+                    let deps = resource::acquire(&access, &result.signal());
+                    if deps.len() == 0 {
+                        gate.pulse();
+                    } else {
+                        Barrier::new(&deps).signal().callback(move || gate.pulse());
+                    }
+                }
+                Ok(result)
+            }
+            Err(Full(task)) => Err(TaskBuilder{
+                task: TaskBox::boxed(task),
+                wait: wait,
+                options: options,
+                access: access,
+                delay: delay,
+                result: result
+            })
+        }
+    }
 }
 
 /// Create a fiber
@@ -36,10 +229,13 @@ pub fn task<F, T:Send+'static>(f: F) -> TaskBuilder<T>
 
     let (future, set) = Future::new();
     TaskBuilder {
-        task: Box::new(move |sched: &mut Schedule| {
+        task: TaskBox::new(move |sched: &mut Schedule| {
             set.set(f(sched));
         }),
         wait: Vec::new(),
+        options: TaskOptions::default(),
+        access: Vec::new(),
+        delay: None,
         result: future
     }
-}
\ No newline at end of file
+}
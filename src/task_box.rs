@@ -0,0 +1,261 @@
+//! Storage for the closure of a task. Small closures are kept inline,
+//! medium ones in recycled blocks, and only the large ones get a box
+//! of their own.
+
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use {Schedule, FnBox};
+
+/// The size of the inline storage, in words
+const INLINE: usize = 4;
+
+/// The size of a recycled block, in words
+const BLOCK: usize = 32;
+
+/// The number of blocks a thread keeps for reuse
+const FREE_BLOCKS: usize = 256;
+
+type Block = [MaybeUninit<usize>; BLOCK];
+
+/// Blocks kept for reuse. Each thread takes its blocks from a list of
+/// its own, and a block goes back to the list it came from, whichever
+/// thread drops it. The tasks submitted from outside of the pools are
+/// dropped by the workers, their blocks still come back.
+type FreeList = Arc<Mutex<Vec<Box<Block>>>>;
+
+thread_local!(static FREE: FreeList = Arc::new(Mutex::new(Vec::new())));
+
+/// A block from this thread's free list or a new one, along with the
+/// list it goes back to.
+fn take_block() -> (Box<Block>, FreeList) {
+    FREE.with(|free| {
+        let block = free.lock().unwrap().pop()
+            .unwrap_or_else(|| Box::new([MaybeUninit::uninit(); BLOCK]));
+        (block, free.clone())
+    })
+}
+
+/// Give a block back to the free list it came from.
+fn give_block(block: Box<Block>, free: FreeList) {
+    let mut free = free.lock().unwrap();
+    if free.len() < FREE_BLOCKS {
+        free.push(block);
+    }
+}
+
+enum Storage {
+    Inline([MaybeUninit<usize>; INLINE]),
+    Block(Box<Block>, FreeList),
+    Boxed(Option<Box<FnBox+Send>>)
+}
+
+/// The closure of a task, see `Schedule::add_task_box`. Closures of up
+/// to four words are stored without an allocation.
+pub struct TaskBox {
+    storage: Storage,
+    /// Runs the closure stored inline or in a block
+    call: unsafe fn(*mut usize, &mut Schedule),
+    /// Drops the closure stored inline or in a block without running it
+    drop: unsafe fn(*mut usize),
+    /// The closure was not run or dropped yet
+    full: bool
+}
+
+// a `TaskBox` is only built from closures that are `Send`
+unsafe impl Send for TaskBox {}
+
+unsafe fn call_data<F: FnOnce(&mut Schedule)>(data: *mut usize, sched: &mut Schedule) {
+    let f = ptr::read(data as *mut F);
+    f(sched)
+}
+
+unsafe fn drop_data<F>(data: *mut usize) {
+    ptr::drop_in_place(data as *mut F)
+}
+
+unsafe fn drop_nothing(_: *mut usize) {}
+
+unsafe fn call_nothing(_: *mut usize, _: &mut Schedule) {}
+
+impl TaskBox {
+    /// Store `f`.
+    pub fn new<F>(f: F) -> TaskBox
+        where F: FnOnce(&mut Schedule) + Send + 'static {
+
+        let size = mem::size_of::<F>();
+        let aligned = mem::align_of::<F>() <= mem::align_of::<usize>();
+        let storage = if aligned && size <= INLINE * mem::size_of::<usize>() {
+            Storage::Inline([MaybeUninit::uninit(); INLINE])
+        } else if aligned && size <= BLOCK * mem::size_of::<usize>() {
+            let (block, free) = take_block();
+            Storage::Block(block, free)
+        } else {
+            return TaskBox::boxed(Box::new(f));
+        };
+
+        let mut task = TaskBox {
+            storage: storage,
+            call: call_data::<F>,
+            drop: drop_data::<F>,
+            full: true
+        };
+        unsafe { ptr::write(task.data() as *mut F, f) };
+        task
+    }
+
+    /// Store a closure that is already boxed.
+    pub fn boxed(task: Box<FnBox+Send>) -> TaskBox {
+        TaskBox {
+            storage: Storage::Boxed(Some(task)),
+            call: call_nothing,
+            drop: drop_nothing,
+            full: true
+        }
+    }
+
+    fn data(&mut self) -> *mut usize {
+        match self.storage {
+            Storage::Inline(ref mut words) => words.as_mut_ptr() as *mut usize,
+            Storage::Block(ref mut block, _) => block.as_mut_ptr() as *mut usize,
+            Storage::Boxed(_) => ptr::null_mut()
+        }
+    }
+
+    /// Run the closure.
+    pub fn call(mut self, sched: &mut Schedule) {
+        // the closure is moved out first, a panic drops it only once
+        self.full = false;
+        if let Storage::Boxed(ref mut task) = self.storage {
+            if let Some(task) = task.take() {
+                task.call_box(sched);
+            }
+            return;
+        }
+        let data = self.data();
+        unsafe { (self.call)(data, sched) }
+    }
+
+    /// Box the closure, for a scheduler that only takes boxes.
+    pub fn into_boxed(mut self) -> Box<FnBox+Send> {
+        if let Storage::Boxed(ref mut task) = self.storage {
+            if let Some(task) = task.take() {
+                return task;
+            }
+        }
+        Box::new(move |sched: &mut Schedule| self.call(sched))
+    }
+}
+
+impl Drop for TaskBox {
+    fn drop(&mut self) {
+        if self.full {
+            self.full = false;
+            let data = self.data();
+            unsafe { (self.drop)(data) }
+        }
+        if let Storage::Block(block, free) = mem::replace(&mut self.storage, Storage::Boxed(None)) {
+            give_block(block, free);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use {Schedule, FnBox};
+    use super::TaskBox;
+
+    struct Nothing;
+
+    impl Schedule for Nothing {
+        fn add_task(&mut self, _: Box<FnBox+Send>, _: Vec<::pulse::Signal>) {}
+    }
+
+    /// Counts how often it was dropped
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// A closure capturing `words` words besides the counter
+    fn closure(words: usize, runs: &Arc<AtomicUsize>, drops: &Arc<AtomicUsize>) -> TaskBox {
+        let runs = runs.clone();
+        let counted = Counted(drops.clone());
+        match words {
+            0 => TaskBox::new(move |_: &mut Schedule| {
+                let _ = &counted;
+                runs.fetch_add(1, Ordering::SeqCst);
+            }),
+            16 => {
+                let padding = [7usize; 16];
+                TaskBox::new(move |_: &mut Schedule| {
+                    let _ = &counted;
+                    assert_eq!(padding[15], 7);
+                    runs.fetch_add(1, Ordering::SeqCst);
+                })
+            }
+            _ => {
+                let padding = [7usize; 64];
+                TaskBox::new(move |_: &mut Schedule| {
+                    let _ = &counted;
+                    assert_eq!(padding[63], 7);
+                    runs.fetch_add(1, Ordering::SeqCst);
+                })
+            }
+        }
+    }
+
+    #[test]
+    fn runs_and_drops_once() {
+        // inline, in a block and boxed
+        for &words in &[0, 16, 64] {
+            let (runs, drops) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+            closure(words, &runs, &drops).call(&mut Nothing);
+            assert_eq!((runs.load(Ordering::SeqCst), drops.load(Ordering::SeqCst)), (1, 1));
+
+            let (runs, drops) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+            drop(closure(words, &runs, &drops));
+            assert_eq!((runs.load(Ordering::SeqCst), drops.load(Ordering::SeqCst)), (0, 1));
+
+            let (runs, drops) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+            closure(words, &runs, &drops).into_boxed().call_box(&mut Nothing);
+            assert_eq!((runs.load(Ordering::SeqCst), drops.load(Ordering::SeqCst)), (1, 1));
+        }
+    }
+
+    fn block_address(task: &TaskBox) -> usize {
+        match task.storage {
+            super::Storage::Block(ref block, _) => &**block as *const _ as usize,
+            _ => panic!("the closure should be in a block")
+        }
+    }
+
+    #[test]
+    fn blocks_are_recycled() {
+        let big = [1usize; 16];
+        let first = TaskBox::new(move |_: &mut Schedule| assert_eq!(big[0], 1));
+        let address = block_address(&first);
+        first.call(&mut Nothing);
+
+        let second = TaskBox::new(move |_: &mut Schedule| assert_eq!(big[0], 1));
+        assert_eq!(block_address(&second), address);
+    }
+
+    #[test]
+    fn blocks_come_back_from_other_threads() {
+        let big = [1usize; 16];
+        let first = TaskBox::new(move |_: &mut Schedule| assert_eq!(big[0], 1));
+        let address = block_address(&first);
+        thread::spawn(move || first.call(&mut Nothing)).join().unwrap();
+
+        let second = TaskBox::new(move |_: &mut Schedule| assert_eq!(big[0], 1));
+        assert_eq!(block_address(&second), address);
+    }
+}
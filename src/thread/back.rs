@@ -2,86 +2,210 @@
 //! on a separate thread. All it does is listening to a command
 //! channel and starting new tasks when the time comes.
 
+use std::cmp;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Sender, SendError, channel};
 use std::thread;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
 
 use pulse::*;
 
-use {Wait, Schedule, FnBox};
+use {Wait, Placement, WorkerId, TaskOptions, FrontendBuilder};
+use {ShutdownReport, StealStats};
+use pool::{Pool, Run};
+use job::Job;
+use affinity;
+use waiting;
 
 /// Task queue back-end.
 pub struct Inner {
     shutdown: bool,
-    running: usize,
-    wake: Option<Pulse>
+    pinned: HashMap<usize, Sender<Job>>
 }
 
-pub struct Backend(Mutex<Inner>);
+pub struct Backend {
+    name: String,
+    inner: Mutex<Inner>,
+    pool: Pool,
+    workers: AtomicUsize,
+    affinity: Option<Vec<usize>>,
+    stack_size: Option<usize>
+}
 
 impl Backend {
     /// Create a new back-end.
-    pub fn new() -> Arc<Backend> {
-        Arc::new(Backend(Mutex::new(Inner{
-            shutdown: false,
-            running: 0,
-            wake: None
-        })))
+    pub fn new(config: &FrontendBuilder) -> Arc<Backend> {
+        Arc::new(Backend {
+            name: config.name.clone(),
+            inner: Mutex::new(Inner{
+                shutdown: false,
+                pinned: HashMap::new()
+            }),
+            pool: Pool::new(config),
+            workers: AtomicUsize::new(config.workers),
+            affinity: config.affinity.clone(),
+            stack_size: config.stack_size
+        })
     }
 
-    /// Start a task that will run once all the Handle's have
-    /// been completed.
-    pub fn start(back: Arc<Backend>, task: Box<FnBox+Send>, mut after: Vec<Signal>) {
-        // Create the wait signal if needed
-        let signal = if after.len() == 0 {
-            Signal::pulsed()
-        } else if after.len() == 1 {
-            after.pop().unwrap()
-        } else {
-            Barrier::new(&after).signal()
-        };
+    /// A builder for a thread of the pool.
+    fn thread(&self, name: String, stack_size: Option<usize>) -> thread::Builder {
+        let builder = thread::Builder::new().name(name);
+        // a task gets at least the stack of the pool
+        match (stack_size, self.stack_size) {
+            (Some(a), Some(b)) => builder.stack_size(cmp::max(a, b)),
+            (Some(size), None) | (None, Some(size)) => builder.stack_size(size),
+            (None, None) => builder
+        }
+    }
 
-        signal.callback(move || {
-            let mut g = back.0.lock().unwrap();
-            if !g.shutdown {
-                let b = back.clone();
-                thread::spawn(move || {
-                    let mut b = b;
-                    task.call_box(&mut b);
-                    let mut g = b.0.lock().unwrap();
-                    g.running -= 1;
-                    if g.running == 0 {
-                        g.wake.take().map(|p| p.pulse());
-                    }
-                });
-                g.running += 1;
-            }
-        });
+    /// Run the task on a thread of its own.
+    fn spawn(back: Arc<Backend>, task: Job, stack_size: Option<usize>) {
+        let g = back.inner.lock().unwrap();
+        if !g.shutdown {
+            let b = back.clone();
+            back.thread(back.name.clone(), stack_size).spawn(move || {
+                // tasks that are not pinned may run on any of the cores
+                if let Some(ref cores) = b.affinity {
+                    affinity::pin_current_thread(cores);
+                }
+                let mut b = b;
+                task.run(&mut b);
+            }).unwrap();
+        }
     }
 
-    /// Kill the backend, wait until the condition is satisfied.
-    pub fn exit(&self, wait: Wait) {
-        let mut g = self.0.lock().unwrap();
+    /// Run the task on the thread owned by worker `index`, the
+    /// thread is created the first time it is needed.
+    fn spawn_pinned(back: Arc<Backend>, index: usize, task: Job) {
+        let mut g = back.inner.lock().unwrap();
+        if g.shutdown {
+            return;
+        }
+        // the task was added before the worker was removed
+        if index > back.workers.load(Ordering::SeqCst) {
+            warn!("Worker {} was removed, running its pinned task elsewhere", index);
+            drop(g);
+            return Backend::spawn(back, task, None);
+        }
 
-        // read the current active count, OR in the BLOCK
-        // flag if needed for the wait
-        match wait {
-            Wait::None | Wait::Active => {
-                g.shutdown = true;
+        let task = match g.pinned.get(&index).map(|send| send.send(task)) {
+            Some(Ok(())) => return,
+            Some(Err(SendError(task))) => {
+                warn!("The thread of worker {} is gone, starting a new one", index);
+                task
             }
-            Wait::Pending => ()
+            None => task
         };
 
-        if g.running != 0 {
-            let (p, t) = Signal::new();
-            g.wake = Some(t);
-            drop(g);
-            p.wait().unwrap();
+        let (send, recv) = channel::<Job>();
+        let b = back.clone();
+        let name = format!("{} {}", back.name, index);
+        let core = back.affinity.as_ref().map(|cores| cores[(index - 1) % cores.len()]);
+        back.thread(name, None).spawn(move || {
+            if let Some(core) = core {
+                affinity::pin_current_thread(&[core]);
+            }
+            let mut b = b;
+            for task in recv.iter() {
+                // a panic is contained to the task, the thread keeps
+                // running the tasks pinned to the worker
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| task.run(&mut b)));
+            }
+        }).unwrap();
+
+        // the receiver is alive until the thread exits, which
+        // can't happen before the channel is closed
+        let _ = send.send(task);
+        g.pinned.insert(index, send);
+    }
+
+    /// Run the tasks that are waiting for the main thread until
+    /// `signal` is asserted, the other tasks have threads of their own.
+    pub fn help(back: &Arc<Backend>, signal: &Signal) {
+        waiting::unpark_on(signal);
+        while signal.is_pending() {
+            if !back.pool.main.run_one(&mut back.clone()) {
+                thread::park_timeout(waiting::IDLE);
+            }
+        }
+    }
+
+    /// List the workers tasks can be pinned to.
+    pub fn workers(&self) -> Vec<WorkerId> {
+        let count = self.workers.load(Ordering::SeqCst);
+        (1..count + 1).map(WorkerId).collect()
+    }
+
+    /// Change the number of workers tasks can be pinned to. The
+    /// threads of the removed workers exit once their queued tasks
+    /// are done.
+    pub fn set_worker_count(back: &Arc<Backend>, count: usize) {
+        assert!(count > 0, "a pool needs at least one worker");
+        back.workers.store(count, Ordering::SeqCst);
+        back.inner.lock().unwrap().pinned.retain(|&index, _| index <= count);
+    }
+
+    /// Every ready task gets a thread, there is nothing to scale.
+    pub fn set_auto_scaling(&self, _range: Option<(usize, usize)>) {}
+
+    /// There is no work stealing, so no steals.
+    pub fn steal_stats(&self) -> StealStats {
+        StealStats::default()
+    }
+
+    /// Kill the backend, wait until the condition is satisfied or
+    /// the timeout expired.
+    pub fn exit(back: &Arc<Backend>, wait: Wait, timeout: Option<Duration>) -> ShutdownReport {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut timed_out = false;
+
+        if wait == Wait::Pending {
+            timed_out = !Pool::flush(back, deadline);
         }
+        back.inner.lock().unwrap().shutdown = true;
+        if wait != Wait::None && !back.pool.counters.wait_idle(deadline) {
+            timed_out = true;
+        }
+
+        // closing the channels lets the pinned threads exit
+        back.inner.lock().unwrap().pinned.clear();
+        back.pool.counters.report(timed_out)
     }
 }
 
-impl<'a> Schedule for Arc<Backend>  {
-    fn add_task(&mut self, task: Box<FnBox+Send>, after: Vec<Signal>) {
-        Backend::start(self.clone(), task, after)
+impl Run for Backend {
+    fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    fn pin(&self, worker: WorkerId) {
+        let count = self.workers.load(Ordering::SeqCst);
+        assert!(worker.0 > 0 && worker.0 <= count,
+                "{:?} is not a worker of the pool, it may have been removed", worker);
+    }
+
+    fn run(back: Arc<Backend>, task: Job, options: TaskOptions, _woken: bool) {
+        match options.placement {
+            Placement::Any => Backend::spawn(back, task, options.stack_size),
+            Placement::MainThread => {
+                if options.stack_size.is_some() {
+                    warn!("Task runs on the main thread, its stack size is ignored");
+                }
+                if !back.inner.lock().unwrap().shutdown {
+                    back.pool.main.push(task);
+                }
+            }
+            Placement::Worker(WorkerId(index)) => {
+                if options.stack_size.is_some() {
+                    warn!("Task pinned to worker {} runs on its thread, \
+                           its stack size is ignored", index);
+                }
+                Backend::spawn_pinned(back, index, task)
+            }
+        }
     }
 }
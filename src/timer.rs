@@ -0,0 +1,144 @@
+//! A timer thread shared by all the pools. It turns deadlines into
+//! signals, so that timed tasks go through the same dependency
+//! tracking as any other task.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::mpsc::{Sender, Receiver, RecvTimeoutError, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pulse::{Signal, Pulse};
+
+use {Schedule, task};
+
+struct Timeout {
+    at: Instant,
+    pulse: Pulse
+}
+
+// ordered so that the BinaryHeap pops the earliest deadline first
+impl Ord for Timeout {
+    fn cmp(&self, other: &Timeout) -> Ordering {
+        other.at.cmp(&self.at)
+    }
+}
+
+impl PartialOrd for Timeout {
+    fn partial_cmp(&self, other: &Timeout) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timeout {
+    fn eq(&self, other: &Timeout) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Timeout {}
+
+fn timer() -> &'static Mutex<Sender<Timeout>> {
+    static TIMER: OnceLock<Mutex<Sender<Timeout>>> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let (send, recv) = channel();
+        thread::Builder::new().name("Timer".to_string()).spawn(move || {
+            run(recv)
+        }).unwrap();
+        Mutex::new(send)
+    })
+}
+
+fn run(recv: Receiver<Timeout>) {
+    let mut queue = BinaryHeap::new();
+    loop {
+        let now = Instant::now();
+        while queue.peek().map(|t: &Timeout| t.at <= now).unwrap_or(false) {
+            queue.pop().unwrap().pulse.pulse();
+        }
+
+        let timeout = match queue.peek() {
+            Some(next) => match recv.recv_timeout(next.at - now) {
+                Ok(timeout) => timeout,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return
+            },
+            None => match recv.recv() {
+                Ok(timeout) => timeout,
+                Err(_) => return
+            }
+        };
+        queue.push(timeout);
+    }
+}
+
+/// Create a signal that is asserted at `deadline`.
+pub fn at(deadline: Instant) -> Signal {
+    if deadline <= Instant::now() {
+        return Signal::pulsed();
+    }
+
+    let (signal, pulse) = Signal::new();
+    timer().lock().unwrap().send(Timeout {
+        at: deadline,
+        pulse: pulse
+    }).unwrap();
+    signal
+}
+
+/// Create a signal that is asserted once `delay` has elapsed.
+pub fn after(delay: Duration) -> Signal {
+    at(Instant::now() + delay)
+}
+
+/// Handle to a periodic task, see `Frontend::every`.
+#[derive(Clone)]
+pub struct Periodic {
+    cancelled: Arc<AtomicBool>
+}
+
+impl Periodic {
+    /// Stop the periodic task. A run that already started completes,
+    /// but no new run is started.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
+
+    /// Check if the periodic task was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+/// Run `f` every `period` until the returned handle is cancelled.
+pub fn every<F>(sched: &mut Schedule, period: Duration, f: F) -> Periodic
+    where F: FnMut(&mut Schedule) + Send + 'static {
+
+    assert!(period > Duration::from_millis(0), "the period must not be zero");
+    let handle = Periodic {
+        cancelled: Arc::new(AtomicBool::new(false))
+    };
+    tick(sched, f, period, Instant::now() + period, handle.clone());
+    handle
+}
+
+fn tick<F>(sched: &mut Schedule, mut f: F, period: Duration, at: Instant, handle: Periodic)
+    where F: FnMut(&mut Schedule) + Send + 'static {
+
+    task(move |sched| {
+        if handle.is_cancelled() {
+            return;
+        }
+        f(sched);
+
+        // ticks that were missed while running late are skipped
+        let mut next = at + period;
+        let now = Instant::now();
+        while next <= now {
+            next = next + period;
+        }
+        tick(sched, f, period, next, handle);
+    }).after(self::at(at)).start(sched);
+}
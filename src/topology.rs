@@ -0,0 +1,142 @@
+//! CPU topology, read from `/sys/devices/system/cpu`. It is used to
+//! make the workers steal from the workers close to them first.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The number of distance tiers between two cores
+pub const TIERS: usize = 3;
+/// The cores share an L2 or L3 cache
+pub const SHARED_CACHE: usize = 0;
+/// The cores are on the same NUMA node
+pub const NODE: usize = 1;
+/// Nothing is known to be shared
+pub const REMOTE: usize = 2;
+
+pub struct Topology {
+    /// For each cpu, the cpus it shares each of its L2+ caches with
+    caches: HashMap<usize, Vec<Vec<usize>>>,
+    /// The NUMA node of each cpu
+    nodes: HashMap<usize, usize>
+}
+
+fn read_trimmed<P: AsRef<Path>>(path: P) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Parse a cpu list like `0-3,8-11`
+fn parse_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in list.split(',').filter(|r| r.len() > 0) {
+        let mut bounds = range.splitn(2, '-').filter_map(|b| b.parse::<usize>().ok());
+        match (bounds.next(), bounds.next()) {
+            (Some(first), Some(last)) => cpus.extend(first..last + 1),
+            (Some(cpu), None) => cpus.push(cpu),
+            _ => ()
+        }
+    }
+    cpus
+}
+
+impl Topology {
+    /// Read the topology of the machine, anything that can't be read
+    /// is treated as remote.
+    pub fn read() -> Topology {
+        let mut topology = Topology {
+            caches: HashMap::new(),
+            nodes: HashMap::new()
+        };
+
+        let entries = match fs::read_dir("/sys/devices/system/cpu") {
+            Ok(entries) => entries,
+            Err(_) => return topology
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let cpu = match name.strip_prefix("cpu").and_then(|n| n.parse().ok()) {
+                Some(cpu) => cpu,
+                None => continue
+            };
+
+            let mut shared = Vec::new();
+            if let Ok(caches) = fs::read_dir(entry.path().join("cache")) {
+                for cache in caches.filter_map(|e| e.ok()) {
+                    let level = read_trimmed(cache.path().join("level"))
+                        .and_then(|l| l.parse::<u32>().ok())
+                        .unwrap_or(0);
+                    if level < 2 {
+                        continue;
+                    }
+                    if let Some(list) = read_trimmed(cache.path().join("shared_cpu_list")) {
+                        shared.push(parse_list(&list));
+                    }
+                }
+            }
+            topology.caches.insert(cpu, shared);
+
+            // the node is a `nodeN` link in the cpu's directory
+            if let Ok(links) = fs::read_dir(entry.path()) {
+                for link in links.filter_map(|e| e.ok()) {
+                    let name = link.file_name().to_string_lossy().into_owned();
+                    if let Some(node) = name.strip_prefix("node").and_then(|n| n.parse().ok()) {
+                        topology.nodes.insert(cpu, node);
+                    }
+                }
+            }
+        }
+        topology
+    }
+
+    /// The distance tier between two cpus.
+    pub fn tier(&self, a: usize, b: usize) -> usize {
+        let shares_cache = self.caches.get(&a)
+            .map(|caches| caches.iter().any(|cpus| cpus.contains(&b)))
+            .unwrap_or(false);
+        let node = self.nodes.get(&a);
+
+        if shares_cache {
+            SHARED_CACHE
+        } else if node.is_some() && node == self.nodes.get(&b) {
+            NODE
+        } else {
+            REMOTE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    #[test]
+    fn parse_ranges_and_single_cpus() {
+        assert_eq!(parse_list("0-3,8,10-11"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_list("5"), vec![5]);
+        assert_eq!(parse_list(""), Vec::<usize>::new());
+        assert_eq!(parse_list("x,2"), vec![2]);
+    }
+
+    #[test]
+    fn tiers() {
+        // cpus 0 and 1 share a cache, 0 to 2 are on node 0, 3 on node 1
+        let mut caches = HashMap::new();
+        caches.insert(0, vec![vec![0, 1]]);
+        caches.insert(1, vec![vec![0, 1]]);
+        let mut nodes = HashMap::new();
+        for cpu in 0..3 {
+            nodes.insert(cpu, 0);
+        }
+        nodes.insert(3, 1);
+        let topology = Topology {
+            caches: caches,
+            nodes: nodes
+        };
+
+        assert_eq!(topology.tier(0, 1), SHARED_CACHE);
+        assert_eq!(topology.tier(0, 2), NODE);
+        assert_eq!(topology.tier(0, 3), REMOTE);
+        assert_eq!(topology.tier(0, 4), REMOTE);
+    }
+}
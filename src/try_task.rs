@@ -0,0 +1,305 @@
+//! Tasks that can fail. A failed task skips the tasks started after
+//! it, their result is the error of the first task that failed.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use pulse::Signal;
+use future_pulse::{Future, Set};
+use {Schedule, TaskBox, WorkerId, Strand, Resource, TaskOptions};
+use task::{task, TaskBuilder};
+use resource::{self, Access};
+use timer;
+
+/// Why a task failed
+#[derive(Clone)]
+enum Failure<E> {
+    Error(E),
+    Panicked
+}
+
+/// Where a task stores its failure, for its dependents to find. The
+/// failure is numbered in the order the failures happened
+type ErrorSlot<E> = Arc<Mutex<Option<(usize, Failure<E>)>>>;
+
+/// Numbers the failures, to tell which one happened first
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+fn next_failure() -> usize {
+    FAILURES.fetch_add(1, Ordering::SeqCst)
+}
+
+/// What the task does when it runs, completed by the builder
+struct Plan<E> {
+    upstream: Vec<ErrorSlot<E>>,
+    retries: usize,
+    backoff: Duration,
+    options: TaskOptions
+}
+
+/// A run of the task, which may be followed by retries
+struct Attempt<T, E> {
+    f: Box<FnMut(&mut Schedule) -> Result<T, E> + Send>,
+    plan: Arc<Mutex<Plan<E>>>,
+    set: Set<Result<T, E>>,
+    error: ErrorSlot<E>
+}
+
+impl<T, E> Attempt<T, E>
+    where T: Send + 'static,
+          E: Clone + Send + 'static {
+
+    /// The first run, skipped if a dependency failed
+    fn first(self, sched: &mut Schedule) {
+        // the dependencies are done, the failure that happened first
+        // wins, whichever dependency was declared first. A failure
+        // passed on keeps its number
+        let failed = self.plan.lock().unwrap().upstream.iter()
+                         .filter_map(|error| error.lock().unwrap().clone())
+                         .min_by_key(|&(order, _)| order);
+        match failed {
+            Some((order, Failure::Error(error))) => self.fail(order, error),
+            Some((order, Failure::Panicked)) => self.panicked(order),
+            None => self.run(sched)
+        }
+    }
+
+    fn run(mut self, sched: &mut Schedule) {
+        let result = {
+            let f = &mut self.f;
+            panic::catch_unwind(AssertUnwindSafe(|| f(sched)))
+        };
+
+        let retry = {
+            let mut plan = self.plan.lock().unwrap();
+            if result.as_ref().map(|r| r.is_ok()).unwrap_or(false) || plan.retries == 0 {
+                None
+            } else {
+                // the wait doubles with each retry
+                plan.retries -= 1;
+                let backoff = plan.backoff;
+                plan.backoff = backoff * 2;
+                Some((backoff, plan.options.clone()))
+            }
+        };
+
+        match (result, retry) {
+            (_, Some((backoff, mut options))) => {
+                // the retry waits on the timer, not on a worker. This
+                // attempt holds a pending slot until it returns, which
+                // the retry takes over rather than wait for
+                options.retry = true;
+                sched.add_task_box(TaskBox::new(move |sched: &mut Schedule| self.run(sched)),
+                                   vec![timer::after(backoff)],
+                                   options);
+            }
+            (Ok(Ok(value)), None) => self.set.set(Ok(value)),
+            (Ok(Err(error)), None) => self.fail(next_failure(), error),
+            (Err(panic), None) => {
+                *self.error.lock().unwrap() = Some((next_failure(), Failure::Panicked));
+                panic::resume_unwind(panic)
+            }
+        }
+    }
+
+    fn fail(self, order: usize, error: E) {
+        *self.error.lock().unwrap() = Some((order, Failure::Error(error.clone())));
+        self.set.set(Err(error));
+    }
+
+    /// Skip the task after a dependency panicked, its result is never set
+    fn panicked(self, order: usize) {
+        *self.error.lock().unwrap() = Some((order, Failure::Panicked));
+    }
+}
+
+/// Something a task started with `try_task` runs after. It is built
+/// from a `Signal`, which never fails, or from a `TryFuture`.
+pub struct Dependency<E> {
+    signal: Signal,
+    error: Option<ErrorSlot<E>>
+}
+
+impl<E> From<Signal> for Dependency<E> {
+    fn from(signal: Signal) -> Dependency<E> {
+        Dependency {
+            signal: signal,
+            error: None
+        }
+    }
+}
+
+impl<'a, T, E> From<&'a TryFuture<T, E>> for Dependency<E> {
+    fn from(future: &'a TryFuture<T, E>) -> Dependency<E> {
+        future.dependency()
+    }
+}
+
+/// The result of a task started with `try_task`
+pub struct TryFuture<T, E> {
+    result: Future<Result<T, E>>,
+    error: ErrorSlot<E>
+}
+
+impl<T, E> TryFuture<T, E> {
+    /// The signal asserted once the task is done, or skipped
+    pub fn signal(&self) -> Signal {
+        self.result.signal()
+    }
+
+    /// Let other tasks run after this one, and fail with its error.
+    /// Unlike a reference to the future, this can be taken before the
+    /// future is moved into the dependent task
+    pub fn dependency(&self) -> Dependency<E> {
+        Dependency {
+            signal: self.signal(),
+            error: Some(self.error.clone())
+        }
+    }
+
+    /// Wait for the result of the task
+    pub fn get(self) -> Result<T, E> {
+        self.result.get()
+    }
+}
+
+/// A structure to help build a task that can fail, see `TaskBuilder`
+pub struct TryTaskBuilder<T, E> {
+    inner: TaskBuilder<()>,
+    /// The resources are held until the last attempt is done, not
+    /// only the first one
+    access: Vec<(Resource, Access)>,
+    plan: Arc<Mutex<Plan<E>>>,
+    result: Future<Result<T, E>>,
+    error: ErrorSlot<E>
+}
+
+impl<T, E> TryTaskBuilder<T, E> {
+    /// Start the task only after `dep` is done. If `dep` is a task
+    /// that failed, this task is skipped and fails with the same error.
+    /// If it panicked, this task is skipped and its result is never set.
+    /// With several failed dependencies, the failure that happened
+    /// first is passed on
+    pub fn after<D: Into<Dependency<E>>>(mut self, dep: D) -> TryTaskBuilder<T, E> {
+        let Dependency{signal, error} = dep.into();
+        if let Some(error) = error {
+            self.plan.lock().unwrap().upstream.push(error);
+        }
+        self.inner = self.inner.after(signal);
+        self
+    }
+
+    /// See `TaskBuilder::delay`
+    pub fn delay(mut self, delay: Duration) -> TryTaskBuilder<T, E> {
+        self.inner = self.inner.delay(delay);
+        self
+    }
+
+    /// See `TaskBuilder::deadline`
+    pub fn deadline(mut self, deadline: Instant) -> TryTaskBuilder<T, E> {
+        self.inner = self.inner.deadline(deadline);
+        self
+    }
+
+    /// See `TaskBuilder::deferrable`
+    pub fn deferrable(mut self) -> TryTaskBuilder<T, E> {
+        self.inner = self.inner.deferrable();
+        self
+    }
+
+    /// See `TaskBuilder::on_main_thread`
+    pub fn on_main_thread(mut self) -> TryTaskBuilder<T, E> {
+        self.inner = self.inner.on_main_thread();
+        self
+    }
+
+    /// See `TaskBuilder::pin_to`
+    pub fn pin_to(mut self, worker: WorkerId) -> TryTaskBuilder<T, E> {
+        self.inner = self.inner.pin_to(worker);
+        self
+    }
+
+    /// See `TaskBuilder::on_strand`
+    pub fn on_strand(self, strand: &Strand) -> TryTaskBuilder<T, E> {
+        self.writes(strand.resource())
+    }
+
+    /// See `TaskBuilder::reads`
+    pub fn reads(mut self, res: &Resource) -> TryTaskBuilder<T, E> {
+        self.access.push((res.clone(), Access::Read));
+        self
+    }
+
+    /// See `TaskBuilder::writes`
+    pub fn writes(mut self, res: &Resource) -> TryTaskBuilder<T, E> {
+        self.access.push((res.clone(), Access::Write));
+        self
+    }
+
+    /// See `TaskBuilder::no_suspend`
+    pub fn no_suspend(mut self) -> TryTaskBuilder<T, E> {
+        self.inner = self.inner.no_suspend();
+        self
+    }
+
+    /// Run the task again if it fails or panics, up to `retries` more
+    /// times. The first retry waits for `backoff`, the wait doubles
+    /// with every retry. The wait doesn't hold a worker
+    pub fn retry(self, retries: usize, backoff: Duration) -> TryTaskBuilder<T, E> {
+        {
+            let mut plan = self.plan.lock().unwrap();
+            plan.retries = retries;
+            plan.backoff = backoff;
+        }
+        self
+    }
+
+    /// Start the task using the supplied scheduler
+    pub fn start(self, sched: &mut Schedule) -> TryFuture<T, E> {
+        // the retries run the same way as the first attempt
+        self.plan.lock().unwrap().options = self.inner.options().clone();
+        let mut inner = self.inner;
+        for signal in resource::acquire(&self.access, &self.result.signal()) {
+            inner = inner.after(signal);
+        }
+        inner.start(sched);
+        TryFuture {
+            result: self.result,
+            error: self.error
+        }
+    }
+}
+
+/// Create a task that can fail. Its error is passed on to the tasks
+/// started after it, which don't run. The task may run more than
+/// once, see `TryTaskBuilder::retry`
+pub fn try_task<F, T, E>(f: F) -> TryTaskBuilder<T, E>
+    where F: FnMut(&mut Schedule) -> Result<T, E> + Send + 'static,
+          T: Send + 'static,
+          E: Clone + Send + 'static {
+
+    let plan = Arc::new(Mutex::new(Plan {
+        upstream: Vec::new(),
+        retries: 0,
+        backoff: Duration::from_millis(0),
+        options: TaskOptions::default()
+    }));
+    let error = Arc::new(Mutex::new(None));
+    let (result, set) = Future::new();
+    let attempt = Attempt {
+        f: Box::new(f),
+        plan: plan.clone(),
+        set: set,
+        error: error.clone()
+    };
+
+    TryTaskBuilder {
+        inner: task(move |sched: &mut Schedule| attempt.first(sched)),
+        access: Vec::new(),
+        plan: plan,
+        result: result,
+        error: error
+    }
+}
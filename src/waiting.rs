@@ -0,0 +1,49 @@
+//! Waiting that does the right thing wherever it is called from. A
+//! fiber is suspended, a worker thread runs its other tasks meanwhile
+//! and any other thread runs the tasks submitted to the pools from
+//! outside of them. In the thread back-end every task has a thread of
+//! its own, so the waiting thread blocks.
+
+use std::thread;
+use std::time::Duration;
+
+use pulse::{Signal, Signals, WaitError};
+use future_pulse::Future;
+
+/// How long a thread that helps while waiting sleeps when it has
+/// nothing to run. It is woken up early when the wait is over, but
+/// not by new tasks.
+pub const IDLE: Duration = Duration::from_millis(1);
+
+/// Wait until `signal` is asserted. In a task this never blocks a
+/// worker that could run the tasks it waits for. Outside of the pools
+/// the thread helps all the fiber pools, not only the one running the
+/// task waited for, see `Frontend::wait_helping` to help a single
+/// pool.
+pub fn wait(signal: Signal) -> Result<(), WaitError> {
+    help(&signal);
+    signal.wait()
+}
+
+/// Wait for the result of `future`, see `wait`.
+pub fn block_on<T: Send + 'static>(future: Future<T>) -> T {
+    let _ = wait(future.signal());
+    future.get()
+}
+
+/// Unpark the calling thread once `signal` is asserted, for the
+/// helping loops that park while they have nothing to run.
+pub fn unpark_on(signal: &Signal) {
+    let thread = thread::current();
+    signal.clone().callback(move || thread.unpark());
+}
+
+#[cfg(feature="fiber")]
+fn help(signal: &Signal) {
+    ::fiber::worker::help(signal)
+}
+
+// every task of the thread back-end has a thread of its own, which
+// may block
+#[cfg(feature="thread")]
+fn help(_: &Signal) {}
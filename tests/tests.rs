@@ -2,11 +2,14 @@ extern crate fibe;
 extern crate timebomb;
 extern crate pulse;
 extern crate future_pulse;
+#[cfg(target_os="linux")]
+extern crate libc;
 
 use fibe::*;
 use pulse::Signals;
 use future_pulse::Future;
 use timebomb::timeout_ms;
+use std::thread;
 
 #[test]
 fn die_empty_none() {
@@ -183,4 +186,890 @@ fn fiber_test_1k() {
         set.set(0);
         assert_eq!(future.get(), 1_000);
     }, 3000);
-}
\ No newline at end of file
+}
+
+#[test]
+fn main_thread_task() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let main = thread::current().id();
+        let a = task(|_| thread::current().id()).start(&mut front);
+        let b = task(|_| thread::current().id())
+                    .after(a.signal())
+                    .on_main_thread()
+                    .start(&mut front);
+
+        while b.signal().is_pending() {
+            front.run_main_thread_tasks();
+        }
+        assert_eq!(b.get(), main);
+    }, 3000);
+}
+
+#[test]
+fn pinned_tasks() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let worker = front.workers()[0];
+        let names: Vec<Future<Option<String>>> = (0..10).map(|_| {
+            task(|_| thread::current().name().map(|s| s.to_string()))
+                .pin_to(worker)
+                .start(&mut front)
+        }).collect();
+
+        let names: Vec<Option<String>> = names.into_iter().map(|f| f.get()).collect();
+        assert!(names[0].is_some());
+        assert!(names.iter().all(|n| *n == names[0]));
+    }, 3000);
+}
+
+#[test]
+fn pinned_task_panics() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let worker = front.workers()[0];
+        let panicked = task(|_| panic!("pinned task panics"))
+            .pin_to(worker)
+            .start(&mut front);
+        let _ = panicked.signal().wait();
+
+        let after = task(|_| 7).pin_to(worker).start(&mut front);
+        assert_eq!(after.get(), 7);
+    }, 3000);
+}
+
+#[test]
+fn child_runs_while_its_parent_keeps_running() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new().workers(2).build();
+        let parent = task(|sched| {
+            let ran = Arc::new(AtomicBool::new(false));
+            let r = ran.clone();
+            task(move |_| r.store(true, Ordering::SeqCst)).start(sched);
+            // spinning keeps this worker busy, another one has to
+            // take the child
+            while !ran.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        }).start(&mut front);
+        parent.get();
+    }, 3000);
+}
+
+#[test]
+fn strand_serializes() {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let strand = front.strand();
+        let busy = Arc::new(AtomicBool::new(false));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let mut last = None;
+        for i in 0..100 {
+            let (busy, order) = (busy.clone(), order.clone());
+            last = Some(task(move |_| {
+                assert!(!busy.swap(true, Ordering::SeqCst));
+                order.lock().unwrap().push(i);
+                busy.store(false, Ordering::SeqCst);
+            }).on_strand(&strand).start(&mut front));
+        }
+        last.unwrap().get();
+
+        let order = order.lock().unwrap();
+        assert_eq!(*order, (0..100).collect::<Vec<_>>());
+    }, 3000);
+}
+
+#[test]
+fn resource_readers_and_writers() {
+    use std::sync::{Arc, Mutex};
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let res = front.resource();
+        let value = Arc::new(Mutex::new(0));
+
+        let mut reads = Vec::new();
+        for i in 0..10 {
+            let v = value.clone();
+            task(move |_| { *v.lock().unwrap() = i; })
+                .writes(&res)
+                .start(&mut front);
+
+            for _ in 0..5 {
+                let v = value.clone();
+                reads.push((i, task(move |_| *v.lock().unwrap())
+                                  .reads(&res)
+                                  .start(&mut front)));
+            }
+        }
+
+        for (expected, read) in reads {
+            assert_eq!(read.get(), expected);
+        }
+    }, 3000);
+}
+
+#[test]
+fn frame_pipeline_bounds_frames_in_flight() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let mut pipeline = front.pipeline(2);
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        for n in 0..10 {
+            let mut frame = pipeline.begin_frame();
+            assert_eq!(frame.index(), n as u64);
+            assert!(completed.load(Ordering::SeqCst) + 1 >= n);
+
+            let done = completed.clone();
+            let previous = frame.previous();
+            frame.start(task(move |_| {
+                thread::sleep(std::time::Duration::from_millis(10));
+                done.fetch_add(1, Ordering::SeqCst);
+            }).after(previous), &mut front);
+            pipeline.end_frame(frame);
+        }
+
+        pipeline.wait_all();
+        assert_eq!(completed.load(Ordering::SeqCst), 10);
+    }, 3000);
+}
+
+#[test]
+fn delayed_task() {
+    use std::time::{Duration, Instant};
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let start = Instant::now();
+        task(|_| {}).delay(Duration::from_millis(100))
+                    .start(&mut front)
+                    .get();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }, 3000);
+}
+
+#[test]
+fn periodic_task() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        let handle = front.every(Duration::from_millis(10), move |_| {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+
+        thread::sleep(Duration::from_millis(200));
+        handle.cancel();
+        thread::sleep(Duration::from_millis(50));
+        let ticks = count.load(Ordering::SeqCst);
+        assert!(ticks > 1);
+
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(count.load(Ordering::SeqCst), ticks);
+    }, 3000);
+}
+
+#[test]
+fn deadline_miss_is_reported() {
+    use std::sync::Mutex;
+    use std::sync::mpsc::channel;
+    use std::time::{Duration, Instant};
+
+    timeout_ms(|| {
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let mut front = FrontendBuilder::new()
+            .scheduling(Scheduling::EarliestDeadline)
+            .on_deadline_miss(move |late| { tx.lock().unwrap().send(late).unwrap(); })
+            .build();
+
+        task(|_| {}).deadline(Instant::now() + Duration::from_secs(60))
+                    .start(&mut front)
+                    .get();
+        task(|_| thread::sleep(Duration::from_millis(10)))
+            .deadline(Instant::now())
+            .start(&mut front);
+
+        assert!(rx.recv().unwrap() > Duration::from_millis(0));
+        assert_eq!(front.deadline_misses(), 1);
+    }, 3000);
+}
+
+#[cfg(feature="fiber")]
+#[test]
+fn earliest_deadline_first() {
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use std::time::{Duration, Instant};
+
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new()
+            .workers(1)
+            .scheduling(Scheduling::EarliestDeadline)
+            .build();
+
+        // the only worker is blocked until all the tasks are ready
+        let (started, pulse) = pulse::Signal::new();
+        let (release, blocked) = channel::<()>();
+        task(move |_| {
+            pulse.pulse();
+            blocked.recv().unwrap();
+        }).no_suspend().start(&mut front);
+        started.wait().unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let now = Instant::now();
+        let done: Vec<pulse::Signal> = vec![3, 1, 2].into_iter().map(|i| {
+            let order = order.clone();
+            task(move |_| order.lock().unwrap().push(i))
+                .deadline(now + Duration::from_secs(i))
+                .start(&mut front)
+                .signal()
+        }).collect();
+        release.send(()).unwrap();
+
+        pulse::Barrier::new(&done).wait().unwrap();
+        assert_eq!(*order.lock().unwrap(), vec![1, 2, 3]);
+    }, 3000);
+}
+
+#[test]
+fn deferrable_task_is_held_over() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        front.set_frame_budget(Some(Duration::from_millis(100)));
+        front.next_frame();
+        thread::sleep(Duration::from_millis(150));
+
+        let deferred = task(|_| {}).deferrable().start(&mut front);
+        let normal = task(|_| {}).start(&mut front);
+        normal.get();
+        thread::sleep(Duration::from_millis(20));
+        assert!(deferred.signal().is_pending());
+
+        assert_eq!(front.next_frame(), 1);
+        deferred.get();
+        assert_eq!(front.postponed_tasks(), 1);
+    }, 3000);
+}
+
+#[test]
+fn try_start_fails_when_full() {
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new()
+            .max_pending(2, Backpressure::Block)
+            .build();
+        let (gate, pulse) = pulse::Signal::new();
+
+        let a = task(|_| 1).after(gate.clone()).start(&mut front);
+        let b = task(|_| 2).after(gate.clone()).start(&mut front);
+        assert_eq!(front.pending_tasks(), Some(2));
+
+        let c = match task(|_| 3).try_start(&mut front) {
+            Ok(_) => panic!("the pool should be full"),
+            Err(c) => c
+        };
+
+        pulse.pulse();
+        assert_eq!(a.get() + b.get(), 3);
+        assert_eq!(c.start(&mut front).get(), 3);
+    }, 3000);
+}
+
+#[test]
+fn die_timeout_report() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let mut last = task(move |_| {}).start(&mut front);
+        for _ in 1..10 {
+            last = task(move |_| {}).after(last.signal()).start(&mut front);
+        }
+        let report = front.die_timeout(Wait::Pending, Duration::from_secs(1));
+        assert_eq!(report, ShutdownReport {
+            completed: 10,
+            dropped: 0,
+            running: 0,
+            timed_out: false
+        });
+    }, 3000);
+}
+
+#[test]
+fn die_timeout_expires() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let (gate, _pulse) = pulse::Signal::new();
+        task(|_| {}).start(&mut front).get();
+        task(|_| {}).after(gate).start(&mut front);
+
+        let report = front.die_timeout(Wait::Pending, Duration::from_millis(50));
+        assert!(report.timed_out);
+        assert_eq!(report.completed, 1);
+        assert_eq!(report.dropped, 1);
+    }, 3000);
+}
+
+#[test]
+fn die_pending_runs_main_thread_tasks() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let main = thread::current().id();
+        let on_main = task(|_| thread::current().id()).on_main_thread().start(&mut front);
+
+        let report = front.die_timeout(Wait::Pending, Duration::from_secs(1));
+        assert_eq!(report, ShutdownReport {
+            completed: 1,
+            dropped: 0,
+            running: 0,
+            timed_out: false
+        });
+        assert_eq!(on_main.get(), main);
+    }, 3000);
+}
+
+#[test]
+fn die_pending_starts_held_over_tasks() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        front.set_frame_budget(Some(Duration::from_millis(10)));
+        front.next_frame();
+        thread::sleep(Duration::from_millis(20));
+        let deferred = task(|_| 5).deferrable().start(&mut front);
+
+        let report = front.die_timeout(Wait::Pending, Duration::from_secs(1));
+        assert_eq!(report, ShutdownReport {
+            completed: 1,
+            dropped: 0,
+            running: 0,
+            timed_out: false
+        });
+        assert_eq!(deferred.get(), 5);
+    }, 3000);
+}
+
+#[test]
+fn spawner_from_other_threads() {
+    timeout_ms(|| {
+        let front = Frontend::new();
+        let threads: Vec<_> = (0..4).map(|i| {
+            let mut spawner = front.spawner();
+            thread::spawn(move || task(move |_| i * 2).start(&mut spawner).get())
+        }).collect();
+
+        let total: u32 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(total, 12);
+    }, 3000);
+}
+
+#[test]
+fn spawner_keeps_pool_alive() {
+    timeout_ms(|| {
+        let front = FrontendBuilder::new()
+            .shutdown(Shutdown::WithLastHandle)
+            .build();
+        let mut spawner = front.spawner();
+        drop(front);
+
+        assert_eq!(task(|_| 42).start(&mut spawner).get(), 42);
+    }, 3000);
+}
+
+#[test]
+fn last_spawner_dropped_in_a_task() {
+    timeout_ms(|| {
+        let front = FrontendBuilder::new()
+            .shutdown(Shutdown::WithLastHandle)
+            .build();
+        let mut spawner = front.spawner();
+        drop(front);
+
+        let (done, pulse) = pulse::Signal::new();
+        let inner = spawner.clone();
+        task(move |_| {
+            drop(inner);
+            pulse.pulse();
+        }).start(&mut spawner);
+        drop(spawner);
+        done.wait().unwrap();
+    }, 3000);
+}
+
+#[test]
+fn continuation_on_another_pool() {
+    timeout_ms(|| {
+        let mut sim = FrontendBuilder::new().name("sim").build();
+        let io = FrontendBuilder::new().name("io").build();
+        let mut io_spawner = io.spawner();
+
+        let name = task(move |s| {
+            let first = task(|_| thread::current().name().map(|n| n.to_string()))
+                            .start(s);
+            let sim_name = task(|_| thread::current().name().map(|n| n.to_string()))
+                               .start(s)
+                               .get();
+            let io_name = task(|_| thread::current().name().map(|n| n.to_string()))
+                              .after(first.signal())
+                              .start(&mut io_spawner)
+                              .get();
+            (first.get(), sim_name, io_name)
+        }).start(&mut sim).get();
+
+        assert!(name.0.unwrap().starts_with("sim"));
+        assert!(name.1.unwrap().starts_with("sim"));
+        assert!(name.2.unwrap().starts_with("io"));
+    }, 3000);
+}
+
+#[test]
+fn change_worker_count() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        front.set_worker_count(1);
+        assert_eq!(front.workers().len(), 1);
+        let mut last = task(|_| 0).start(&mut front);
+        for _ in 0..100 {
+            last = task(move |_| last.get() + 1).start(&mut front);
+        }
+
+        front.set_worker_count(4);
+        assert_eq!(front.workers().len(), 4);
+        assert_eq!(last.get(), 100);
+
+        front.set_worker_count(2);
+        assert_eq!(front.workers().len(), 2);
+        assert_eq!(task(|_| 1).start(&mut front).get(), 1);
+    }, 3000);
+}
+
+#[test]
+fn pin_to_removed_worker() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new().workers(4).build();
+        let workers = front.workers();
+        front.set_worker_count(1);
+        let removed = workers[3];
+        assert!(!front.workers().contains(&removed));
+
+        let started = panic::catch_unwind(AssertUnwindSafe(|| {
+            task(|_| {}).pin_to(removed).start(&mut front)
+        }));
+        assert!(started.is_err());
+
+        // the ids of the removed workers are given to the new ones
+        front.set_worker_count(4);
+        assert_eq!(front.workers(), workers);
+        assert_eq!(task(|_| 1).pin_to(removed).start(&mut front).get(), 1);
+    }, 3000);
+}
+
+#[test]
+fn auto_scaling_keeps_pinned_workers() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new().workers(4).build();
+        let worker = *front.workers().last().unwrap();
+        task(|_| {}).pin_to(worker).start(&mut front).get();
+
+        front.set_auto_scaling(Some((1, 4)));
+        thread::sleep(Duration::from_millis(1000));
+        assert!(front.workers().contains(&worker));
+        assert_eq!(task(|_| 2).pin_to(worker).start(&mut front).get(), 2);
+    }, 3000);
+}
+
+#[cfg(target_os="linux")]
+#[test]
+fn workers_pinned_to_cores() {
+    use std::mem;
+
+    // a core this process is allowed to run on
+    let core = unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        assert_eq!(libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set), 0);
+        (0..libc::CPU_SETSIZE as usize).find(|&core| libc::CPU_ISSET(core, &set)).unwrap()
+    };
+
+    timeout_ms(move || {
+        let mut front = FrontendBuilder::new()
+            .workers(2)
+            .affinity(vec![core])
+            .build();
+        assert_eq!(front.workers().len(), 2);
+
+        let cpus: Vec<Future<i32>> = (0..10).map(|_| {
+            task(|_| unsafe { libc::sched_getcpu() }).start(&mut front)
+        }).collect();
+        for cpu in cpus {
+            assert_eq!(cpu.get(), core as i32);
+        }
+    }, 3000);
+}
+
+#[test]
+fn no_suspend_task() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let child = task(|sched| {
+            task(|_| 2).no_suspend().start(sched).signal()
+        }).no_suspend().start(&mut front);
+        assert_eq!(task(|_| 1).no_suspend().after(child.get()).start(&mut front).get(), 1);
+    }, 3000);
+}
+
+#[test]
+fn no_suspend_task_waits_helping() {
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new().workers(1).build();
+        let outer = task(|sched| {
+            let inner = task(|_| 3).start(sched);
+            block_on(inner) + 1
+        }).no_suspend().start(&mut front);
+        assert_eq!(outer.get(), 4);
+    }, 3000);
+}
+
+fn recurse(depth: usize) -> usize {
+    let frame = std::hint::black_box([depth as u8; 512]);
+    if depth == 0 {
+        0
+    } else {
+        recurse(depth - 1) + frame[depth % 512] as usize % 2
+    }
+}
+
+#[test]
+fn large_stack_task() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let result = task(|_| recurse(20_000)).stack_size(64 << 20).start(&mut front);
+        assert_eq!(result.get(), 10_000);
+    }, 3000);
+}
+
+#[test]
+fn many_large_stack_tasks() {
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new().workers(2).build();
+        let results: Vec<Future<usize>> = (0..8).map(|i| {
+            task(move |_| recurse(20_000) + i).stack_size(64 << 20).start(&mut front)
+        }).collect();
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result.get(), 10_000 + i);
+        }
+    }, 3000);
+}
+
+#[test]
+fn block_on_in_a_task() {
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new().workers(1).build();
+        let result = task(|sched| {
+            let child = task(|_| 2).start(sched);
+            block_on(child) + 1
+        }).no_suspend().start(&mut front);
+        assert_eq!(block_on(result), 3);
+
+        let done = task(|_| {}).start(&mut front);
+        wait(done.signal()).unwrap();
+    }, 3000);
+}
+
+#[test]
+fn block_on_outside_of_the_pool_helps() {
+    use std::sync::mpsc::channel;
+
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new().workers(1).build();
+        let (started, pulse) = pulse::Signal::new();
+        let (release, blocked) = channel::<()>();
+        task(move |_| {
+            pulse.pulse();
+            blocked.recv().unwrap();
+        }).no_suspend().start(&mut front);
+        started.wait().unwrap();
+
+        // the only worker is blocked, so this thread runs the task
+        assert_eq!(block_on(task(|_| 5).start(&mut front)), 5);
+        release.send(()).unwrap();
+    }, 3000);
+}
+
+#[test]
+fn wait_helping_runs_main_thread_tasks() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let on_main = task(|_| thread::current().name().map(|s| s.to_string()))
+            .on_main_thread()
+            .start(&mut front);
+        let result = task(move |_| on_main.get()).start(&mut front);
+        front.wait_helping(&result);
+        assert_eq!(result.get(), thread::current().name().map(|s| s.to_string()));
+    }, 3000);
+}
+
+#[test]
+fn failed_task_skips_dependents() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let load = try_task(|_| -> Result<u32, String> {
+            Err("missing file".to_string())
+        }).start(&mut front);
+        let r = ran.clone();
+        let parse = try_task(move |_| -> Result<u32, String> {
+            r.fetch_add(1, Ordering::SeqCst);
+            Ok(1)
+        }).after(&load).start(&mut front);
+        let fine = try_task(|_| -> Result<u32, String> { Ok(2) }).start(&mut front);
+        let r = ran.clone();
+        let last = try_task(move |_| -> Result<u32, String> {
+            r.fetch_add(1, Ordering::SeqCst);
+            Ok(3)
+        }).after(&fine).after(&parse).start(&mut front);
+
+        assert_eq!(last.get(), Err("missing file".to_string()));
+        assert_eq!(fine.get(), Ok(2));
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }, 3000);
+}
+
+#[test]
+fn first_error_wins() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let late = try_task(|_| -> Result<(), &'static str> {
+            thread::sleep(Duration::from_millis(50));
+            Err("late")
+        }).start(&mut front);
+        let early = try_task(|_| -> Result<(), &'static str> { Err("early") })
+            .start(&mut front);
+        let passed_on = try_task(|_| -> Result<(), &'static str> { Ok(()) })
+            .after(&early)
+            .start(&mut front);
+
+        // the late error is declared first
+        let last = try_task(|_| -> Result<(), &'static str> { Ok(()) })
+            .after(&late)
+            .after(&passed_on)
+            .start(&mut front);
+        assert_eq!(last.get(), Err("early"));
+    }, 3000);
+}
+
+#[test]
+fn retry_failed_task() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let mut attempts = 0;
+        let result = try_task(move |_| -> Result<u32, u32> {
+            attempts += 1;
+            if attempts < 3 {
+                Err(attempts)
+            } else {
+                Ok(attempts)
+            }
+        }).retry(5, Duration::from_millis(1)).start(&mut front);
+        assert_eq!(result.get(), Ok(3));
+
+        let result = try_task(|_| -> Result<u32, u32> { Err(1) })
+            .retry(2, Duration::from_millis(1))
+            .start(&mut front);
+        assert_eq!(result.get(), Err(1));
+    }, 3000);
+}
+
+#[test]
+fn retry_with_max_pending() {
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = FrontendBuilder::new()
+            .max_pending(1, Backpressure::Block)
+            .build();
+        let mut attempts = 0;
+        let result = try_task(move |_| -> Result<u32, u32> {
+            attempts += 1;
+            if attempts < 3 {
+                Err(attempts)
+            } else {
+                Ok(attempts)
+            }
+        }).retry(5, Duration::from_millis(1)).start(&mut front);
+        assert_eq!(result.get(), Ok(3));
+        assert_eq!(task(|_| 1).start(&mut front).get(), 1);
+    }, 3000);
+}
+
+#[test]
+fn panicked_task_skips_dependents() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let ran = Arc::new(AtomicBool::new(false));
+
+        let failed = try_task(|_| -> Result<u32, u32> { panic!("task panics") })
+            .retry(1, Duration::from_millis(1))
+            .start(&mut front);
+        let r = ran.clone();
+        let dependent = try_task(move |_| -> Result<u32, u32> {
+            r.store(true, Ordering::SeqCst);
+            Ok(1)
+        }).after(&failed).start(&mut front);
+
+        let _ = dependent.signal().wait();
+        assert!(!ran.load(Ordering::SeqCst));
+    }, 3000);
+}
+
+#[test]
+fn retried_task_keeps_its_strand() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let strand = front.strand();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let (o, mut attempts) = (order.clone(), 0);
+        let first = try_task(move |_| -> Result<(), ()> {
+            attempts += 1;
+            if attempts < 3 {
+                return Err(());
+            }
+            o.lock().unwrap().push(1);
+            Ok(())
+        }).retry(2, Duration::from_millis(5)).on_strand(&strand).start(&mut front);
+        let o = order.clone();
+        let second = task(move |_| o.lock().unwrap().push(2))
+            .on_strand(&strand)
+            .start(&mut front);
+
+        second.get();
+        assert_eq!(first.get(), Ok(()));
+        assert_eq!(*order.lock().unwrap(), vec![1, 2]);
+    }, 3000);
+}
+
+#[test]
+fn task_cache_shares_tasks() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let cache = TaskCache::new(Eviction::Frames(1));
+        let started = Arc::new(AtomicUsize::new(0));
+
+        let spawn = |front: &mut Frontend, key: u32| {
+            let started = started.clone();
+            cache.get_or_spawn(key, front, move |_| {
+                started.fetch_add(1, Ordering::SeqCst);
+                key * 2
+            })
+        };
+        let a = spawn(&mut front, 1);
+        let b = spawn(&mut front, 1);
+        let c = spawn(&mut front, 2);
+        assert_eq!((a.get(), b.get(), c.get()), (2, 2, 4));
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+
+        cache.next_frame();
+        assert_eq!(cache.len(), 0);
+        assert_eq!(spawn(&mut front, 1).get(), 2);
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+    }, 3000);
+}
+
+#[test]
+fn task_cache_lru() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let cache = TaskCache::new(Eviction::Lru(2));
+        cache.get_or_spawn(1, &mut front, |_| 1);
+        cache.get_or_spawn(2, &mut front, |_| 2);
+        assert!(cache.get(&1).is_some());
+        cache.get_or_spawn(3, &mut front, |_| 3);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&2).is_none());
+        assert_eq!(cache.get(&1).unwrap().get(), 1);
+    }, 3000);
+}
+
+#[test]
+fn shared_future_fan_out() {
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let mesh = task(|_| vec![1, 2, 3]).start_shared(&mut front);
+
+        let readers: Vec<Future<usize>> = (0..10).map(|i| {
+            let (mesh, ready) = (mesh.clone(), mesh.signal());
+            task(move |_| mesh.get_arc().len() + i)
+                .after(ready)
+                .start(&mut front)
+        }).collect();
+        for (i, reader) in readers.into_iter().enumerate() {
+            assert_eq!(reader.get(), 3 + i);
+        }
+        assert!(mesh.is_ready());
+        assert_eq!(mesh.get(), vec![1, 2, 3]);
+    }, 3000);
+}
+
+#[test]
+fn shared_task_panics() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    timeout_ms(|| {
+        let mut front = Frontend::new();
+        let shared = task(|_| -> u32 { panic!("shared task panics") }).start_shared(&mut front);
+        let cache = TaskCache::new(Eviction::Lru(1));
+        let cached = cache.get_or_spawn(1, &mut front, |_| -> u32 { panic!("cached task panics") });
+
+        for future in vec![shared, cached] {
+            let _ = future.signal().wait();
+            assert!(future.is_ready());
+            assert!(panic::catch_unwind(AssertUnwindSafe(|| future.get_arc())).is_err());
+        }
+        assert_eq!(task(|_| 1).start(&mut front).get(), 1);
+    }, 3000);
+}